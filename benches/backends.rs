@@ -0,0 +1,75 @@
+use std::time::Duration;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+use data_driven_simulation::simulation::{Simulation, BACKENDS};
+use data_driven_simulation::simulation::datadriven::{State, NARROWPHASES};
+use data_driven_simulation::simulation::broadphase::{BroadphaseKind, BROADPHASES};
+use data_driven_simulation::presets;
+
+// Steps the same scenes with both backends and every broadphase. Each sample builds the scene again and
+// times STEPS steps from the start, so every backend and broadphase does exactly the same work.
+// The narrowphase group steps a grid large enough to fill many lanes with each datadriven narrowphase,
+// and only times the narrowphase part of the steps.
+// Run with `cargo bench`, or `cargo bench -- pool_break/oop` for a part of it
+
+
+const STEPS: usize = 10;
+const DT: f32 = 0.01;
+
+
+fn backends(c: &mut Criterion) {
+    for &(scene, build) in presets::PRESETS {
+        let mut group = c.benchmark_group(scene);
+        group.sample_size(10);
+
+        for &backend in BACKENDS {
+            for &broadphase in BROADPHASES {
+                let setup = || {
+                    let mut state = backend.new_state();
+                    state.set_broadphase(broadphase);
+                    build(state.as_mut());
+                    state
+                };
+
+                group.bench_function(BenchmarkId::new(backend.name(), broadphase), |b| b.iter_batched(setup, |mut state| {
+                    for _ in 0..STEPS {
+                        state.step(DT);
+                    }
+                    state
+                }, BatchSize::LargeInput));
+            }
+        }
+
+        group.finish();
+    }
+}
+
+
+fn narrowphases(c: &mut Criterion) {
+    let mut group = c.benchmark_group("narrowphase");
+    group.sample_size(10);
+
+    for &narrowphase in NARROWPHASES {
+        group.bench_function(BenchmarkId::new("grid_100x100", narrowphase), |b| b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let mut state = State::new();
+                state.narrowphase = narrowphase;
+                state.set_broadphase(BroadphaseKind::from_name("grid").unwrap());
+                presets::grid(100, 100, &mut state);
+
+                for _ in 0..STEPS {
+                    state.step(DT);
+                }
+                total += state.timings().narrowphase;
+            }
+            total
+        }));
+    }
+
+    group.finish();
+}
+
+
+criterion_group!(benches, backends, narrowphases);
+criterion_main!(benches);
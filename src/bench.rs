@@ -0,0 +1,269 @@
+use std::time::{Duration, Instant};
+
+use crate::{simulation as sim, presets, cli};
+use sim::{Simulation, Backend, Timings};
+use sim::broadphase::BroadphaseKind;
+use sim::datadriven::{Narrowphase, NARROWPHASES};
+
+// Headless benchmark. Steps a scene without rendering and reports steps per second, the spread of the
+// step times and how long each phase of the step took on average
+
+
+pub const USAGE: &str = "bench [scene file] [--balls count] [--backend name] [--broadphase name] [--narrowphase name] [--dt seconds] [--steps count | --duration seconds] [--json]";
+
+const DEFAULT_BALLS: usize = 10000;
+const DEFAULT_DT: f32 = 0.01;
+const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+
+#[derive(Debug, Clone, Copy)]
+pub enum Length {
+    Steps(usize),
+    Duration(Duration),
+}
+
+
+#[derive(Debug)]
+pub struct BenchArgs {
+    // scene file, without one a grid of balls spheres in a walled box
+    pub scene_path: Option<String>,
+    pub balls: Option<usize>,
+    pub backend: Option<Backend>,
+    pub broadphase: Option<BroadphaseKind>,
+    // only the datadriven backend has more than one
+    pub narrowphase: Option<Narrowphase>,
+    pub dt: f32,
+    pub length: Length,
+    pub json: bool,
+}
+
+impl Default for BenchArgs {
+    fn default() -> Self {
+        Self {
+            scene_path: None,
+            balls: None,
+            backend: None,
+            broadphase: None,
+            narrowphase: None,
+            dt: DEFAULT_DT,
+            length: Length::Duration(DEFAULT_DURATION),
+            json: false,
+        }
+    }
+}
+
+impl BenchArgs {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut res = Self::default();
+
+        while let Some(arg) = args.next() {
+            if arg == "--balls" {
+                res.balls = Some(cli::value(&mut args, &arg)?);
+            } else if arg == "--backend" {
+                res.backend = Some(cli::backend(&mut args, &arg)?);
+            } else if arg == "--broadphase" {
+                res.broadphase = Some(cli::broadphase(&mut args, &arg)?);
+            } else if arg == "--narrowphase" {
+                let name: String = cli::value(&mut args, &arg)?;
+                res.narrowphase = Some(Narrowphase::from_name(&name).ok_or_else(|| {
+                    let names : Vec::<&str> = NARROWPHASES.iter().map(|n| n.name()).collect();
+                    format!("unknown narrowphase '{}', expected one of {}", name, names.join(", "))
+                })?);
+            } else if arg == "--dt" {
+                res.dt = cli::dt(&mut args, &arg)?;
+            } else if arg == "--steps" {
+                let steps = cli::value(&mut args, &arg)?;
+                if steps == 0 {
+                    return Err("--steps must be at least 1".to_string());
+                }
+                res.length = Length::Steps(steps);
+            } else if arg == "--duration" {
+                let seconds: f32 = cli::value(&mut args, &arg)?;
+                if !(seconds > 0.0 && seconds.is_finite()) {
+                    return Err("--duration must be a positive number of seconds".to_string());
+                }
+                res.length = Length::Duration(Duration::from_secs_f32(seconds));
+            } else if arg == "--json" {
+                res.json = true;
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option '{}'", arg));
+            } else if res.scene_path.is_none() {
+                res.scene_path = Some(arg);
+            } else {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+        }
+
+        if res.scene_path.is_some() && res.balls.is_some() {
+            return Err("--balls only applies to the grid scene, not a scene file".to_string());
+        }
+
+        Ok(res)
+    }
+}
+
+pub fn run(args: &BenchArgs) -> Result<(), String> {
+    let (scene, mut state) = match &args.scene_path {
+        Some(path) => (path.clone(), crate::load_state(&args.scene_path, args.backend, args.broadphase)?),
+        None => {
+            let mut state = args.backend.unwrap_or_default().new_state();
+            if let Some(broadphase) = args.broadphase {
+                state.set_broadphase(broadphase);
+            }
+
+            // the grid scene fills a square with an even side
+            let balls = args.balls.unwrap_or(DEFAULT_BALLS);
+            let mut side = (balls as f32).sqrt().ceil() as i32;
+            side += side % 2;
+            presets::grid(side, side, state.as_mut());
+            ("grid".to_string(), state)
+        }
+    };
+
+    if let Some(narrowphase) = args.narrowphase {
+        if state.narrowphase().is_none() {
+            return Err(format!("--narrowphase only applies to the datadriven backend, not {}", state.backend()));
+        }
+        state.set_narrowphase(narrowphase);
+    }
+
+    state.reset_timings();
+
+    let mut step_times = vec![];
+    let start = Instant::now();
+    // always at least one step, a duration shorter than a step would leave nothing to report
+    loop {
+        let time = Instant::now();
+        state.step(args.dt);
+        step_times.push(time.elapsed());
+
+        let done = match args.length {
+            Length::Steps(steps) => step_times.len() >= steps,
+            Length::Duration(duration) => start.elapsed() >= duration,
+        };
+        if done {
+            break;
+        }
+    }
+
+    let report = Report::new(scene, state.as_ref(), args.dt, start.elapsed(), step_times);
+    if args.json {
+        println!("{}", report.json());
+    } else {
+        print!("{}", report.text());
+    }
+
+    Ok(())
+}
+
+
+struct Report {
+    scene: String,
+    backend: Backend,
+    broadphase: BroadphaseKind,
+    narrowphase: Option<Narrowphase>,
+    spheres: usize,
+    dt: f32,
+    steps: usize,
+    elapsed: Duration,
+    // sorted
+    step_times: Vec::<Duration>,
+    timings: Timings,
+}
+
+impl Report {
+
+    fn new(scene: String, state: &dyn Simulation, dt: f32, elapsed: Duration, mut step_times: Vec::<Duration>) -> Self {
+        step_times.sort();
+        Self {
+            scene,
+            backend: state.backend(),
+            broadphase: state.broadphase(),
+            narrowphase: state.narrowphase(),
+            spheres: state.sphere_count(),
+            dt,
+            steps: step_times.len(),
+            elapsed,
+            step_times,
+            timings: state.timings(),
+        }
+    }
+
+    fn steps_per_second(&self) -> f64 {
+        self.steps as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn mean_ms(&self, total: Duration) -> f64 {
+        total.as_secs_f64() * 1000.0 / self.steps as f64
+    }
+
+    // nearest rank
+    fn percentile_ms(&self, p: f64) -> f64 {
+        let rank = ((p / 100.0 * self.steps as f64).ceil() as usize).clamp(1, self.steps);
+        self.step_times[rank - 1].as_secs_f64() * 1000.0
+    }
+
+    fn step_stats(&self) -> [(&'static str, f64); 6] {
+        let total: Duration = self.step_times.iter().sum();
+        [
+            ("mean", self.mean_ms(total)),
+            ("min", self.percentile_ms(0.0)),
+            ("p50", self.percentile_ms(50.0)),
+            ("p90", self.percentile_ms(90.0)),
+            ("p99", self.percentile_ms(99.0)),
+            ("max", self.percentile_ms(100.0)),
+        ]
+    }
+
+    // mean ms/step of each phase, other is the part of the step outside the phases
+    fn phases(&self) -> [(&'static str, f64); 6] {
+        let t = &self.timings;
+        let total: Duration = self.step_times.iter().sum();
+        [
+            ("tree_rebuild", self.mean_ms(t.tree_rebuild)),
+            ("broadphase", self.mean_ms(t.broadphase)),
+            ("narrowphase", self.mean_ms(t.narrowphase)),
+            ("walls", self.mean_ms(t.walls)),
+            ("integration", self.mean_ms(t.integration)),
+            ("other", self.mean_ms(total.saturating_sub(t.total()))),
+        ]
+    }
+
+    fn text(&self) -> String {
+        let stats = self.step_stats().iter().map(|(name, ms)| format!("{} {:.3}", name, ms)).collect::<Vec::<_>>().join("  ");
+        let phases = self.phases().iter().map(|(name, ms)| format!("{} {:.3}", name.replace('_', " "), ms)).collect::<Vec::<_>>().join("  ");
+
+        let narrowphase = self.narrowphase.map(|n| format!(", narrowphase {}", n)).unwrap_or_default();
+
+        format!("scene {}, backend {}, broadphase {}{}, {} spheres, dt {}\n{} steps in {:.2} s, {:.2} steps/s\nms/step: {}\nphases ms/step: {}\n",
+                self.scene, self.backend, self.broadphase, narrowphase, self.spheres, self.dt,
+                self.steps, self.elapsed.as_secs_f64(), self.steps_per_second(),
+                stats,
+                phases)
+    }
+
+    fn json(&self) -> String {
+        let object = |values: &[(&str, f64)]| values.iter().map(|(name, ms)| format!("\"{}\": {:.6}", name, ms)).collect::<Vec::<_>>().join(", ");
+
+        let narrowphase = self.narrowphase.map(|n| format!("\"{}\"", n)).unwrap_or_else(|| "null".to_string());
+
+        format!("{{\"scene\": \"{}\", \"backend\": \"{}\", \"broadphase\": \"{}\", \"narrowphase\": {}, \"spheres\": {}, \"dt\": {}, \"steps\": {}, \"seconds\": {:.6}, \"steps_per_second\": {:.6}, \"ms_per_step\": {{{}}}, \"phases_ms_per_step\": {{{}}}}}",
+                escape(&self.scene), self.backend, self.broadphase, narrowphase, self.spheres, self.dt,
+                self.steps, self.elapsed.as_secs_f64(), self.steps_per_second(),
+                object(&self.step_stats()),
+                object(&self.phases()))
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut res = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res
+}
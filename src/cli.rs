@@ -0,0 +1,59 @@
+use std::str::FromStr;
+
+use crate::presets;
+use crate::simulation::Backend;
+use crate::simulation::broadphase::BroadphaseKind;
+
+// Option values shared by the headless subcommands. Each reads the value following option from args,
+// so every subcommand accepts the same values and reports the same errors
+
+
+pub fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, option: &str) -> Result<T, String> {
+    let s = args.next().ok_or_else(|| format!("{} expects a value", option))?;
+    s.parse().map_err(|_| format!("invalid value '{}' for {}", s, option))
+}
+
+/// Name of a preset, checked to exist
+pub fn preset(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    let name: String = value(args, option)?;
+    if presets::from_name(&name).is_none() {
+        return Err(format!("unknown preset '{}', expected one of {}", name,
+                           presets::PRESETS.iter().map(|(n, _)| *n).collect::<Vec::<_>>().join("|")));
+    }
+    Ok(name)
+}
+
+pub fn backend(args: &mut impl Iterator<Item = String>, option: &str) -> Result<Backend, String> {
+    let name: String = value(args, option)?;
+    Backend::from_name(&name).ok_or_else(|| format!("unknown backend '{}'", name))
+}
+
+pub fn broadphase(args: &mut impl Iterator<Item = String>, option: &str) -> Result<BroadphaseKind, String> {
+    let name: String = value(args, option)?;
+    BroadphaseKind::from_name(&name).ok_or_else(|| format!("unknown broadphase '{}'", name))
+}
+
+/// Time step in seconds, finite and greater than 0
+pub fn dt(args: &mut impl Iterator<Item = String>, option: &str) -> Result<f32, String> {
+    let dt: f32 = value(args, option)?;
+    if !(dt > 0.0 && dt.is_finite()) {
+        return Err(format!("{} must be a positive number of seconds", option));
+    }
+    Ok(dt)
+}
+
+pub fn tolerance(args: &mut impl Iterator<Item = String>, option: &str) -> Result<f32, String> {
+    let tolerance: f32 = value(args, option)?;
+    if tolerance.is_nan() || tolerance < 0.0 {
+        return Err(format!("{} must not be negative", option));
+    }
+    Ok(tolerance)
+}
+
+/// A scene file and a preset are two ways to pick the scene, only one can be given
+pub fn scene_or_preset(scene_path: &Option<String>, preset: &Option<String>) -> Result<(), String> {
+    if scene_path.is_some() && preset.is_some() {
+        return Err("give a scene file or --preset, not both".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::simulation::{Simulation, Backend, EntityId, V3};
+use crate::simulation::broadphase::BroadphaseKind;
+use crate::{presets, scene, cli};
+
+// Steps the same scene with the datadriven and the oop backend and measures how far each sphere's position and
+// velocity drift apart, so a change that alters the physics of one backend, not just its layout, shows up.
+// Spheres are matched by id after every step
+
+
+pub const USAGE: &str = "compare [scene file] [--preset name] [--broadphase name] [--steps count] [--dt seconds] [--tolerance distance]";
+
+const DEFAULT_STEPS: usize = 100;
+const DEFAULT_DT: f32 = 0.01;
+const DEFAULT_TOLERANCE: f32 = 1e-4;
+
+// spheres over the tolerance listed in the report, the ones that diverged first
+const MAX_LISTED: usize = 10;
+
+
+#[derive(Debug)]
+pub struct CompareArgs {
+    // scene file or preset, without either every preset is compared
+    pub scene_path: Option<String>,
+    pub preset: Option<String>,
+    // both backends use it, otherwise each uses its own default or the one in the scene file
+    pub broadphase: Option<BroadphaseKind>,
+    pub steps: usize,
+    pub dt: f32,
+    pub tolerance: f32,
+}
+
+impl CompareArgs {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut res = CompareArgs {
+            scene_path: None,
+            preset: None,
+            broadphase: None,
+            steps: DEFAULT_STEPS,
+            dt: DEFAULT_DT,
+            tolerance: DEFAULT_TOLERANCE,
+        };
+
+        while let Some(arg) = args.next() {
+            if arg == "--preset" {
+                res.preset = Some(cli::preset(&mut args, &arg)?);
+            } else if arg == "--broadphase" {
+                res.broadphase = Some(cli::broadphase(&mut args, &arg)?);
+            } else if arg == "--steps" {
+                res.steps = cli::value(&mut args, &arg)?;
+            } else if arg == "--dt" {
+                res.dt = cli::dt(&mut args, &arg)?;
+            } else if arg == "--tolerance" {
+                res.tolerance = cli::tolerance(&mut args, &arg)?;
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option '{}'", arg));
+            } else if res.scene_path.is_none() {
+                res.scene_path = Some(arg);
+            } else {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+        }
+
+        cli::scene_or_preset(&res.scene_path, &res.preset)?;
+
+        Ok(res)
+    }
+}
+
+/// Compares the scene or presets picked by args, returns the scene name and comparison of each
+pub fn run(args: &CompareArgs) -> Result<Vec::<(String, Comparison)>, String> {
+    let names: Vec::<String> = match (&args.scene_path, &args.preset) {
+        (Some(path), _) => vec![path.clone()],
+        (None, Some(name)) => vec![name.clone()],
+        (None, None) => presets::PRESETS.iter().map(|(name, _)| name.to_string()).collect(),
+    };
+
+    let mut res = vec![];
+    for name in names {
+        let mut a = load(args, &name, Backend::DataDriven)?;
+        let mut b = load(args, &name, Backend::Oop)?;
+        let comparison = compare(a.as_mut(), b.as_mut(), args.steps, args.dt, args.tolerance)?;
+        res.push((name, comparison));
+    }
+
+    Ok(res)
+}
+
+// name is the scene file when one was given, otherwise a preset
+fn load(args: &CompareArgs, name: &str, backend: Backend) -> Result<Box<dyn Simulation>, String> {
+    let mut state = match presets::from_name(name).filter(|_| args.scene_path.is_none()) {
+        Some(build) => {
+            let mut state = backend.new_state();
+            build(state.as_mut());
+            state
+        },
+        None => scene::load(name, Some(backend)).map_err(|e| format!("{}: {}", name, e))?,
+    };
+
+    if let Some(broadphase) = args.broadphase {
+        state.set_broadphase(broadphase);
+    }
+
+    Ok(state)
+}
+
+
+/// Largest divergence of one sphere over all compared steps
+#[derive(Debug, Clone, Copy)]
+pub struct SphereDivergence {
+    pub id: EntityId,
+    pub pos: f32,
+    pub vel: f32,
+    /// first step after which position or velocity was more than the tolerance apart
+    pub first_exceeded: Option<usize>,
+}
+
+
+#[derive(Debug)]
+pub struct Comparison {
+    pub a: Backend,
+    pub b: Backend,
+    pub steps: usize,
+    pub tolerance: f32,
+    /// in the order of the spheres in a
+    pub spheres: Vec::<SphereDivergence>,
+}
+
+impl Comparison {
+
+    pub fn within_tolerance(&self) -> bool {
+        self.spheres.iter().all(|s| s.first_exceeded.is_none())
+    }
+
+    pub fn max_pos(&self) -> Option<&SphereDivergence> {
+        self.spheres.iter().max_by(|x, y| x.pos.total_cmp(&y.pos))
+    }
+
+    pub fn max_vel(&self) -> Option<&SphereDivergence> {
+        self.spheres.iter().max_by(|x, y| x.vel.total_cmp(&y.vel))
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} vs {}, {} spheres, {} steps", self.a, self.b, self.spheres.len(), self.steps)?;
+
+        if let (Some(pos), Some(vel)) = (self.max_pos(), self.max_vel()) {
+            write!(f, ", max position divergence {:e}", pos.pos)?;
+            if pos.pos > 0.0 {
+                write!(f, " (sphere {})", pos.id)?;
+            }
+            write!(f, ", max velocity divergence {:e}", vel.vel)?;
+            if vel.vel > 0.0 {
+                write!(f, " (sphere {})", vel.id)?;
+            }
+        }
+
+        let mut exceeded: Vec::<_> = self.spheres.iter().filter(|s| s.first_exceeded.is_some()).collect();
+        if exceeded.is_empty() {
+            return write!(f, ", within tolerance {:e}", self.tolerance);
+        }
+
+        write!(f, ", {} spheres over tolerance {:e}", exceeded.len(), self.tolerance)?;
+        exceeded.sort_by_key(|s| (s.first_exceeded, s.id));
+        for s in exceeded.iter().take(MAX_LISTED) {
+            write!(f, "\n  sphere {} from step {}: position {:e}, velocity {:e}", s.id, s.first_exceeded.unwrap(), s.pos, s.vel)?;
+        }
+        if exceeded.len() > MAX_LISTED {
+            write!(f, "\n  and {} more", exceeded.len() - MAX_LISTED)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Steps a and b steps times, comparing every sphere's position and velocity before the first step and after each.
+/// Both must hold the same spheres, with the same ids
+pub fn compare(a: &mut dyn Simulation, b: &mut dyn Simulation, steps: usize, dt: f32, tolerance: f32) -> Result<Comparison, String> {
+
+    let mut res = Comparison {
+        a: a.backend(),
+        b: b.backend(),
+        steps,
+        tolerance,
+        spheres: a.snapshot().spheres.iter().map(|s| SphereDivergence { id: s.id, pos: 0.0, vel: 0.0, first_exceeded: None }).collect(),
+    };
+
+    for step in 0..=steps {
+        if step > 0 {
+            a.step(dt);
+            b.step(dt);
+        }
+
+        let snapshot_a = a.snapshot();
+        let snapshot_b = b.snapshot();
+
+        if snapshot_a.spheres.len() != res.spheres.len() || snapshot_b.spheres.len() != res.spheres.len() {
+            return Err(format!("step {}: {} has {} spheres and {} has {}, expected {}", step,
+                               res.a, snapshot_a.spheres.len(), res.b, snapshot_b.spheres.len(), res.spheres.len()));
+        }
+
+        let index_b: HashMap::<EntityId, usize> = snapshot_b.spheres.iter().enumerate().map(|(i, s)| (s.id, i)).collect();
+
+        for (s, d) in snapshot_a.spheres.iter().zip(res.spheres.iter_mut()) {
+            let other = match index_b.get(&s.id) {
+                Some(&i) => &snapshot_b.spheres[i],
+                None => return Err(format!("step {}: sphere {} is missing in {}", step, s.id, res.b)),
+            };
+
+            let pos = divergence(&s.pos, &other.pos);
+            let vel = divergence(&s.vel, &other.vel);
+            d.pos = d.pos.max(pos);
+            d.vel = d.vel.max(vel);
+
+            if d.first_exceeded.is_none() && (pos > tolerance || vel > tolerance) {
+                d.first_exceeded = Some(step);
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+// distance between a and b, a sphere that is NaN or inf in only one of them is infinitely far off
+fn divergence(a: &V3, b: &V3) -> f32 {
+    if a.iter().zip(b.iter()).all(|(x, y)| x.to_bits() == y.to_bits()) {
+        return 0.0;
+    }
+
+    let d = (a - b).norm();
+    if d.is_nan() { f32::INFINITY } else { d }
+}
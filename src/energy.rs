@@ -0,0 +1,154 @@
+use crate::{simulation as sim, presets, cli};
+use sim::{Simulation, Backend};
+use sim::broadphase::BroadphaseKind;
+use sim::diagnostics::{Diagnostics, EnergyMonitor};
+
+// Headless conservation check. Steps a scene and reports how total energy and momentum changed. With a tolerance
+// every step where the energy rose by more than it is listed, and the exit code is 1 when there are any
+
+
+pub const USAGE: &str = "energy [scene file] [--preset name] [--backend name] [--broadphase name] [--dt seconds] [--steps count] [--log file] [--tolerance relative]";
+
+const DEFAULT_DT: f32 = 0.01;
+const DEFAULT_STEPS: usize = 1000;
+
+// flagged steps listed in the report, the first ones
+const MAX_LISTED: usize = 10;
+
+
+#[derive(Debug)]
+pub struct EnergyArgs {
+    // scene file or preset, without either every preset is checked
+    pub scene_path: Option<String>,
+    pub preset: Option<String>,
+    pub backend: Option<Backend>,
+    pub broadphase: Option<BroadphaseKind>,
+    pub dt: f32,
+    pub steps: usize,
+    // csv with the totals of every step, only for a single scene
+    pub log: Option<String>,
+    // flag steps where the energy rises by more than this times the energy before the step
+    pub tolerance: Option<f32>,
+}
+
+impl EnergyArgs {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut res = EnergyArgs {
+            scene_path: None,
+            preset: None,
+            backend: None,
+            broadphase: None,
+            dt: DEFAULT_DT,
+            steps: DEFAULT_STEPS,
+            log: None,
+            tolerance: None,
+        };
+
+        while let Some(arg) = args.next() {
+            if arg == "--preset" {
+                res.preset = Some(cli::preset(&mut args, &arg)?);
+            } else if arg == "--backend" {
+                res.backend = Some(cli::backend(&mut args, &arg)?);
+            } else if arg == "--broadphase" {
+                res.broadphase = Some(cli::broadphase(&mut args, &arg)?);
+            } else if arg == "--dt" {
+                res.dt = cli::dt(&mut args, &arg)?;
+            } else if arg == "--steps" {
+                res.steps = cli::value(&mut args, &arg)?;
+            } else if arg == "--log" {
+                res.log = Some(cli::value(&mut args, &arg)?);
+            } else if arg == "--tolerance" {
+                res.tolerance = Some(cli::tolerance(&mut args, &arg)?);
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option '{}'", arg));
+            } else if res.scene_path.is_none() {
+                res.scene_path = Some(arg);
+            } else {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+        }
+
+        cli::scene_or_preset(&res.scene_path, &res.preset)?;
+
+        if res.log.is_some() && res.scene_path.is_none() && res.preset.is_none() {
+            return Err("--log needs a scene file or --preset".to_string());
+        }
+
+        Ok(res)
+    }
+}
+
+/// Checks the scene or presets picked by args and prints a report of each, returns false when a step was flagged
+pub fn run(args: &EnergyArgs) -> Result<bool, String> {
+    let names: Vec::<String> = match (&args.scene_path, &args.preset) {
+        (Some(path), _) => vec![path.clone()],
+        (None, Some(name)) => vec![name.clone()],
+        (None, None) => presets::PRESETS.iter().map(|(name, _)| name.to_string()).collect(),
+    };
+
+    let mut ok = true;
+    for name in names {
+        let mut state = load(args, &name)?;
+
+        let err = |e: std::io::Error| format!("{}: {}", args.log.as_deref().unwrap_or(&name), e);
+        let mut monitor = EnergyMonitor::new(args.log.as_deref(), args.tolerance).map_err(err)?;
+
+        let start = monitor.observe(state.as_ref()).map_err(err)?;
+        let mut end = start;
+        for _ in 0..args.steps {
+            state.step(args.dt);
+            end = monitor.observe(state.as_ref()).map_err(err)?;
+        }
+        monitor.flush().map_err(err)?;
+
+        print!("{}", report(&name, state.as_ref(), args, &start, &end, &monitor));
+        ok &= monitor.rises().is_empty();
+    }
+
+    Ok(ok)
+}
+
+// name is the scene file when one was given, otherwise a preset
+fn load(args: &EnergyArgs, name: &str) -> Result<Box<dyn Simulation>, String> {
+    match presets::from_name(name).filter(|_| args.scene_path.is_none()) {
+        Some(build) => {
+            let mut state = args.backend.unwrap_or_default().new_state();
+            if let Some(broadphase) = args.broadphase {
+                state.set_broadphase(broadphase);
+            }
+            build(state.as_mut());
+            Ok(state)
+        },
+        None => crate::load_state(&Some(name.to_string()), args.backend, args.broadphase),
+    }
+}
+
+fn report(name: &str, state: &dyn Simulation, args: &EnergyArgs, start: &Diagnostics, end: &Diagnostics, monitor: &EnergyMonitor) -> String {
+    let v3 = |v: &sim::V3| format!("({:e}, {:e}, {:e})", v.x, v.y, v.z);
+
+    let mut res = format!("{}: backend {}, broadphase {}, {} spheres, {} steps of {}\n", name, state.backend(), state.broadphase(), state.sphere_count(), args.steps, args.dt);
+    res += &format!("  energy {:e} -> {:e} (kinetic {:e} -> {:e}, potential {:e} -> {:e})\n",
+                    start.total_energy(), end.total_energy(), start.kinetic_energy, end.kinetic_energy, start.potential_energy, end.potential_energy);
+    res += &format!("  linear momentum {} -> {}\n", v3(&start.linear_momentum), v3(&end.linear_momentum));
+    res += &format!("  angular momentum {} -> {}\n", v3(&start.angular_momentum), v3(&end.angular_momentum));
+
+    let tolerance = match args.tolerance {
+        Some(tolerance) => tolerance,
+        None => return res,
+    };
+
+    let rises = monitor.rises();
+    if rises.is_empty() {
+        res += &format!("  energy never rose more than {:e}\n", tolerance);
+        return res;
+    }
+
+    res += &format!("  energy rose more than {:e} in {} steps\n", tolerance, rises.len());
+    for rise in rises.iter().take(MAX_LISTED) {
+        res += &format!("    step {}: {:e} -> {:e}\n", rise.step, rise.before, rise.after);
+    }
+    if rises.len() > MAX_LISTED {
+        res += &format!("    and {} more\n", rises.len() - MAX_LISTED);
+    }
+    res
+}
@@ -0,0 +1,6 @@
+// The simulation as a library, so the benches can build and step the same scenes as the app
+pub mod simulation;
+pub mod scene;
+pub mod presets;
+pub mod compare;
+pub mod cli;
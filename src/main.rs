@@ -1,102 +1,264 @@
-mod simulation;
+mod bench;
+mod record;
+mod energy;
+use data_driven_simulation::{simulation, scene, presets, compare, cli};
 use simulation as sim;
+use sim::{Simulation, Backend, BACKENDS};
+use sim::broadphase::{BroadphaseKind, BROADPHASES};
+#[cfg(feature = "sdl")]
+use sim::trajectory::{Recorder, Trajectory};
 use nalgebra::vector;
-use nalgebra as na;
-use std::time::Instant;
 
 
 #[cfg(feature = "sdl")]
 mod render;
 
 fn main() {
-    #[cfg(not(feature = "sdl"))]
-    flamegraph();
-
-    let mut state = collision();
-
-    #[cfg(feature = "sdl")]
-    render::run_with_render(state);
-}
 
+    let mut args = std::env::args().skip(1).peekable();
+
+    if args.peek().is_some_and(|arg| arg == "compare") {
+        args.next();
+        let compare_args = match compare::CompareArgs::parse(args) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("{}", err);
+                usage();
+            }
+        };
+
+        match compare::run(&compare_args) {
+            Ok(comparisons) => {
+                for (scene, comparison) in &comparisons {
+                    println!("{}: {}", scene, comparison);
+                }
+                if !comparisons.iter().all(|(_, c)| c.within_tolerance()) {
+                    std::process::exit(1);
+                }
+            },
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-pub fn collision() -> sim::State {
-    let mut state = sim::State::new();
+    if args.peek().is_some_and(|arg| arg == "bench") {
+        args.next();
+        let bench_args = match bench::BenchArgs::parse(args) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("{}", err);
+                usage();
+            }
+        };
+
+        if let Err(err) = bench::run(&bench_args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    //add_grid(10, 30, &mut state);
+    if args.peek().is_some_and(|arg| arg == "record") {
+        args.next();
+        let record_args = match record::RecordArgs::parse(args) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("{}", err);
+                usage();
+            }
+        };
+
+        if let Err(err) = record::run(&record_args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
 
+    if args.peek().is_some_and(|arg| arg == "energy") {
+        args.next();
+        let energy_args = match energy::EnergyArgs::parse(args) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("{}", err);
+                usage();
+            }
+        };
+
+        match energy::run(&energy_args) {
+            Ok(true) => {},
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    //state.add_ball(vector![30.0, 0.0, 2.0], vector![-150.0,0.0,0.0], 1.0, 0.5);
+    let args = match Args::parse(args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            usage();
+        }
+    };
 
-    state.add_ball(vector![30.0, 0.0, 0.0], vector![-200.0,0.0,0.0], 1.0, 0.5);
+    // nothing to render without sdl, record or bench the scene headless instead
+    #[cfg(not(feature = "sdl"))]
+    if args.replay.is_some() {
+        eprintln!("--replay needs the renderer, build with the sdl feature");
+        std::process::exit(1);
+    } else if let Some(out_path) = args.record {
+        let record_args = record::RecordArgs { scene_path: args.scene_path, backend: args.backend, broadphase: args.broadphase, every: args.record_every, ..record::RecordArgs::new(out_path) };
+        if let Err(err) = record::run(&record_args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    } else {
+        let bench_args = bench::BenchArgs { scene_path: args.scene_path, backend: args.backend, broadphase: args.broadphase, ..Default::default() };
+        if let Err(err) = bench::run(&bench_args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 
-    //state.add_ball(vector![30.0, 0.0, -2.0], vector![-500.0,0.0,0.0], 1.0, 0.5);
+    #[cfg(feature = "sdl")]
+    let state = match load_state(&args.scene_path, args.backend, args.broadphase) {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
+    #[cfg(feature = "sdl")]
+    let recorder = match &args.record {
+        Some(path) => match Recorder::create(path, args.record_every) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => None
+    };
 
-    state.add_wall(vector![0.0, 0.0, 0.0], vector![1.0, 5.0, 10.0]);
+    #[cfg(feature = "sdl")]
+    let trajectory = match &args.replay {
+        Some(path) => match Trajectory::read(path) {
+            Ok(trajectory) if trajectory.frames.is_empty() => {
+                eprintln!("{}: no recorded steps", path);
+                std::process::exit(1);
+            },
+            Ok(trajectory) => Some(trajectory),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        },
+        None => None
+    };
 
-    state
+    #[cfg(feature = "sdl")]
+    render::run_with_render(state, args.scene_path, args.backend, args.broadphase, recorder, trajectory);
+}
 
 
+fn usage() -> ! {
+    eprintln!("usage: [scene file] [--backend {}] [--broadphase {}] [--record file] [--record-every n] [--replay file]",
+              BACKENDS.iter().map(|b| b.name()).collect::<Vec::<_>>().join("|"),
+              BROADPHASES.iter().map(|b| b.name()).collect::<Vec::<_>>().join("|"));
+    eprintln!("       {}", bench::USAGE);
+    eprintln!("       {}", compare::USAGE);
+    eprintln!("       {}", record::USAGE);
+    eprintln!("       {}", energy::USAGE);
+    std::process::exit(1);
 }
 
-fn wall_test() -> sim::State {
-
-    let mut state = sim::State::new();
 
-    state.add_ball(vector![-5.0, 0.0, 0.0], vector![5.0,0.0,0.0], 1.0, 2.0);
+struct Args {
+    // optional scene file, fall back to the built in collision scene
+    scene_path: Option<String>,
+    // overrides the backend from the scene file
+    backend: Option<Backend>,
+    // overrides the broadphase from the scene file
+    broadphase: Option<BroadphaseKind>,
+    // trajectory file written while stepping, csv or binary when it ends in .bin
+    record: Option<String>,
+    // record every nth step
+    record_every: usize,
+    // trajectory file played back instead of simulating, the scene gives walls, planes and sphere radii
+    replay: Option<String>,
+}
 
-    state.add_wall(vector![0.0, 0.0, 0.0], vector![1.0, 2.0, 10.0]);
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut res = Args { scene_path: None, backend: None, broadphase: None, record: None, record_every: 1, replay: None };
+
+        while let Some(arg) = args.next() {
+            if arg == "--backend" {
+                res.backend = Some(cli::backend(&mut args, &arg)?);
+            } else if arg == "--broadphase" {
+                res.broadphase = Some(cli::broadphase(&mut args, &arg)?);
+            } else if arg == "--record" {
+                res.record = Some(args.next().ok_or_else(|| "--record expects a file".to_string())?);
+            } else if arg == "--record-every" {
+                let n = args.next().ok_or_else(|| "--record-every expects a number".to_string())?;
+                res.record_every = n.parse().ok().filter(|&n| n > 0).ok_or_else(|| format!("invalid value '{}' for --record-every", n))?;
+            } else if arg == "--replay" {
+                res.replay = Some(args.next().ok_or_else(|| "--replay expects a file".to_string())?);
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option '{}'", arg));
+            } else if res.scene_path.is_none() {
+                res.scene_path = Some(arg);
+            } else {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+        }
 
-    state.add_wall(vector![-7.0, 0.0, 0.0], vector![1.0, 2.0, 10.0]);
+        if res.record.is_some() && res.replay.is_some() {
+            return Err("--record and --replay can't be combined".to_string());
+        }
 
-    state
+        Ok(res)
+    }
 }
 
 
-fn flamegraph() {
-    let mut state = sim::State::new();
+pub fn load_state(scene_path: &Option<String>, backend: Option<Backend>, broadphase: Option<BroadphaseKind>) -> Result<Box<dyn Simulation>, String> {
+    let mut state = match scene_path {
+        Some(path) => scene::load(path, backend).map_err(|e| format!("{}: {}", path, e))?,
+        None => collision(backend.unwrap_or_default())
+    };
 
-    add_grid(100, 100, &mut state);
+    if let Some(broadphase) = broadphase {
+        state.set_broadphase(broadphase);
+    }
 
-    let mut time_inst = Instant::now();
+    Ok(state)
+}
 
-    let mut iters = 0;
 
-    let mut time_total = Instant::now();
+pub fn collision(backend: Backend) -> Box<dyn Simulation> {
+    let mut state = backend.new_state();
 
-    loop {
-        sim::step(&mut state, 0.01);
-        iters += 1;
-        if time_inst.elapsed().as_millis() as f32 > 1000.0 {
-            let ms_pr_iter = (time_inst.elapsed().as_millis() as f32) / (iters as f32) ;
-            println!("ms/iter: {:.2?}", iters);
-            time_inst = Instant::now();
-            iters = 0
-        }
-        if time_total.elapsed().as_secs() > 10 {
-            println!("Stopping");
-            break;
+    //add_grid(10, 30, &mut state);
 
-        }
-    }
 
-}
+    //state.add_ball(vector![30.0, 0.0, 2.0], vector![-150.0,0.0,0.0], 1.0, 0.5);
 
+    state.add_ball(vector![30.0, 0.0, 0.0], vector![-200.0,0.0,0.0], 1.0, 0.5);
 
-fn add_grid(x: i32, y: i32, state: &mut sim::State) {
+    //state.add_ball(vector![30.0, 0.0, -2.0], vector![-500.0,0.0,0.0], 1.0, 0.5);
 
-    state.add_wall(vector![-128.0, 0.0, 0.0], vector![10.0, 256.0, 10.0]);
 
-    state.add_wall(vector![128.0, 0.0, 0.0], vector![10.0, 256.0, 10.0]);
+    state.add_wall(vector![0.0, 0.0, 0.0], vector![1.0, 5.0, 10.0]);
 
-    state.add_wall(vector![0.0, -128.0, 0.0], vector![256.0, 10.0, 10.0]);
+    state
 
-    state.add_wall(vector![0.0, 128.0, 0.0], vector![256.0, 10.0, 10.0]);
 
-    for x in (-x/2)..(x/2) {
-        for y in (-y/2)..(y/2) {
-            //state.add_ball(vector![x as f32, y as f32, 0.0], vector![ 0.0, 0.0, 0.0], 0.1, 0.001);
-            state.add_ball(vector![x as f32, y as f32, 0.0], vector![1.0*(y as f32), 1.0/ f32::max(x as f32, 1.0), 0.0], 1.0, 1.0);
-        }
-    }
 }
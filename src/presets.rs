@@ -0,0 +1,138 @@
+use nalgebra as na;
+use nalgebra::vector;
+
+use crate::simulation::Simulation;
+
+// Scenes built in code, the same for every backend, used by the bench subcommand and the criterion benches.
+// All of them are deterministic, so two runs of a scene can be compared step by step
+
+
+/// Adds a scene to an empty state
+pub type Build = fn(&mut dyn Simulation);
+
+/// Every preset at the size used by the benches and comparisons, by name
+pub const PRESETS: &[(&str, Build)] = &[
+    ("grid", |state| grid(60, 60, state)),
+    ("pool_break", |state| pool_break(40, state)),
+    ("dense_pile", |state| dense_pile(12, 12, 12, state)),
+    ("wall_arena", |state| wall_arena(20, 40, state)),
+];
+
+pub fn from_name(name: &str) -> Option<Build> {
+    PRESETS.iter().find(|(n, _)| *n == name).map(|(_, build)| *build)
+}
+
+/// Grid of x * y spheres at z = 0 moving in different directions, inside four walls.
+/// The spheres start a hair apart, so neighbouring rows only touch once they start sliding past each other
+pub fn grid(x: i32, y: i32, state: &mut dyn Simulation) {
+    let r = 1.0;
+    let spacing = 2.0 * r + 0.01;
+
+    // walls 10 thick, with room around the spheres
+    let half = x.max(y) as f32 * spacing / 2.0 + 10.0;
+    state.add_wall(vector![-half - 5.0, 0.0, 0.0], vector![10.0, 2.0 * half + 20.0, 10.0]);
+    state.add_wall(vector![half + 5.0, 0.0, 0.0], vector![10.0, 2.0 * half + 20.0, 10.0]);
+    state.add_wall(vector![0.0, -half - 5.0, 0.0], vector![2.0 * half + 20.0, 10.0, 10.0]);
+    state.add_wall(vector![0.0, half + 5.0, 0.0], vector![2.0 * half + 20.0, 10.0, 10.0]);
+
+    for x in (-x/2)..(x/2) {
+        for y in (-y/2)..(y/2) {
+            state.add_ball(vector![x as f32 * spacing, y as f32 * spacing, 0.0], vector![1.0*(y as f32), 1.0/ f32::max(x as f32, 1.0), 0.0], r, 1.0);
+        }
+    }
+}
+
+
+/// Triangle rack of touching spheres, rows deep, hit head on by a fast cue ball, inside cushion walls
+pub fn pool_break(rows: usize, state: &mut dyn Simulation) {
+    let r = 1.0;
+    // a hair more than touching, so the rack starts at rest and only the cue ball has energy
+    let spacing = 2.0 * r + 0.01;
+    let row_step = spacing * 3.0f32.sqrt() / 2.0;
+
+    for row in 0..rows {
+        for k in 0..=row {
+            let x = row as f32 * row_step;
+            let y = (k as f32 - row as f32 / 2.0) * spacing;
+            state.add_ball(vector![x, y, 0.0], vector![0.0, 0.0, 0.0], r, 1.0);
+        }
+    }
+
+    state.add_ball(vector![-40.0, 0.0, 0.0], vector![150.0, 0.0, 0.0], r, 1.0);
+
+    let length = rows as f32 * row_step + 100.0;
+    let width = rows as f32 * spacing + 40.0;
+    let center_x = rows as f32 * row_step / 2.0 - 20.0;
+
+    state.add_wall(vector![center_x - length / 2.0, 0.0, 0.0], vector![2.0, width, 10.0]);
+    state.add_wall(vector![center_x + length / 2.0, 0.0, 0.0], vector![2.0, width, 10.0]);
+    state.add_wall(vector![center_x, -width / 2.0, 0.0], vector![length, 2.0, 10.0]);
+    state.add_wall(vector![center_x, width / 2.0, 0.0], vector![length, 2.0, 10.0]);
+}
+
+
+/// Block of x * y * z spheres, y layers high, falling onto a floor under gravity, inside four walls. Every other layer
+/// is shifted by half a sphere so the spheres settle into a pile instead of staying in columns
+pub fn dense_pile(x: usize, y: usize, z: usize, state: &mut dyn Simulation) {
+    let r = 1.0;
+    let spacing = 2.0 * r + 0.1;
+
+    state.set_gravity(vector![0.0, -9.81, 0.0]);
+    state.add_plane(vector![0.0, 0.0, 0.0], vector![0.0, 1.0, 0.0], true);
+
+    let half_x = x as f32 * spacing / 2.0 + spacing;
+    let half_z = z as f32 * spacing / 2.0 + spacing;
+    let height = y as f32 * spacing * 2.0;
+
+    state.add_wall(vector![-half_x - 1.0, height / 2.0, 0.0], vector![2.0, height, 2.0 * half_z + 4.0]);
+    state.add_wall(vector![half_x + 1.0, height / 2.0, 0.0], vector![2.0, height, 2.0 * half_z + 4.0]);
+    state.add_wall(vector![0.0, height / 2.0, -half_z - 1.0], vector![2.0 * half_x + 4.0, height, 2.0]);
+    state.add_wall(vector![0.0, height / 2.0, half_z + 1.0], vector![2.0 * half_x + 4.0, height, 2.0]);
+
+    for layer in 0..y {
+        let shift = if layer % 2 == 0 { 0.0 } else { r / 2.0 };
+        for i in 0..x {
+            for k in 0..z {
+                let pos = vector![
+                    (i as f32 - x as f32 / 2.0 + 0.5) * spacing + shift,
+                    r + layer as f32 * spacing,
+                    (k as f32 - z as f32 / 2.0 + 0.5) * spacing + shift
+                ];
+                state.add_ball(pos, vector![0.0, 0.0, 0.0], r, 1.0);
+            }
+        }
+    }
+}
+
+
+/// Square arena with a grid of posts * posts rotated walls and balls * balls spheres moving between them,
+/// for scenes where wall collisions dominate
+pub fn wall_arena(posts: usize, balls: usize, state: &mut dyn Simulation) {
+    let post_spacing = 8.0;
+    let half = posts as f32 * post_spacing / 2.0;
+
+    state.add_wall(vector![-half - 1.0, 0.0, 0.0], vector![2.0, 2.0 * half + 4.0, 10.0]);
+    state.add_wall(vector![half + 1.0, 0.0, 0.0], vector![2.0, 2.0 * half + 4.0, 10.0]);
+    state.add_wall(vector![0.0, -half - 1.0, 0.0], vector![2.0 * half + 4.0, 2.0, 10.0]);
+    state.add_wall(vector![0.0, half + 1.0, 0.0], vector![2.0 * half + 4.0, 2.0, 10.0]);
+
+    for i in 0..posts {
+        for k in 0..posts {
+            let center = vector![(i as f32 + 0.5) * post_spacing - half, (k as f32 + 0.5) * post_spacing - half, 0.0];
+            let angle = (i * posts + k) as f32 * 0.7;
+            let rotation = na::UnitQuaternion::from_axis_angle(&na::Vector3::z_axis(), angle);
+            state.add_rotated_wall(center, vector![3.0, 1.0, 10.0], rotation);
+        }
+    }
+
+    // spheres start on the lines between the posts
+    let ball_spacing = 2.0 * half / balls as f32;
+    for i in 0..balls {
+        for k in 0..balls {
+            let pos = vector![(i as f32 + 0.5) * ball_spacing - half, (k as f32 + 0.5) * ball_spacing - half, 0.0];
+            let angle = (i * balls + k) as f32 * 2.4;
+            let vel = vector![angle.cos(), angle.sin(), 0.0] * 10.0;
+            state.add_ball(pos, vel, 0.4, 0.5);
+        }
+    }
+}
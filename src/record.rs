@@ -0,0 +1,107 @@
+use crate::{simulation as sim, presets, cli};
+use sim::Backend;
+use sim::broadphase::BroadphaseKind;
+use sim::trajectory::Recorder;
+
+// Headless recording. Steps a scene without rendering and writes every sphere's id, position and velocity to a
+// trajectory file, csv or binary when the file ends in .bin
+
+
+pub const USAGE: &str = "record <output file> [scene file] [--preset name] [--backend name] [--broadphase name] [--dt seconds] [--steps count] [--every n]";
+
+const DEFAULT_DT: f32 = 0.01;
+const DEFAULT_STEPS: usize = 1000;
+
+
+#[derive(Debug)]
+pub struct RecordArgs {
+    pub out_path: String,
+    // scene file or preset, without either the built in collision scene
+    pub scene_path: Option<String>,
+    pub preset: Option<String>,
+    pub backend: Option<Backend>,
+    pub broadphase: Option<BroadphaseKind>,
+    pub dt: f32,
+    pub steps: usize,
+    // record every nth step
+    pub every: usize,
+}
+
+impl RecordArgs {
+    pub fn new(out_path: String) -> Self {
+        Self {
+            out_path,
+            scene_path: None,
+            preset: None,
+            backend: None,
+            broadphase: None,
+            dt: DEFAULT_DT,
+            steps: DEFAULT_STEPS,
+            every: 1,
+        }
+    }
+
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut out_path = None;
+        let mut res = Self::new(String::new());
+
+        while let Some(arg) = args.next() {
+            if arg == "--preset" {
+                res.preset = Some(cli::preset(&mut args, &arg)?);
+            } else if arg == "--backend" {
+                res.backend = Some(cli::backend(&mut args, &arg)?);
+            } else if arg == "--broadphase" {
+                res.broadphase = Some(cli::broadphase(&mut args, &arg)?);
+            } else if arg == "--dt" {
+                res.dt = cli::dt(&mut args, &arg)?;
+            } else if arg == "--steps" {
+                res.steps = cli::value(&mut args, &arg)?;
+            } else if arg == "--every" {
+                res.every = cli::value(&mut args, &arg)?;
+                if res.every == 0 {
+                    return Err("--every must be at least 1".to_string());
+                }
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option '{}'", arg));
+            } else if out_path.is_none() {
+                out_path = Some(arg);
+            } else if res.scene_path.is_none() {
+                res.scene_path = Some(arg);
+            } else {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+        }
+
+        res.out_path = out_path.ok_or_else(|| "record expects an output file".to_string())?;
+
+        cli::scene_or_preset(&res.scene_path, &res.preset)?;
+
+        Ok(res)
+    }
+}
+
+pub fn run(args: &RecordArgs) -> Result<(), String> {
+    let mut state = match args.preset.as_deref().and_then(presets::from_name) {
+        Some(build) => {
+            let mut state = args.backend.unwrap_or_default().new_state();
+            if let Some(broadphase) = args.broadphase {
+                state.set_broadphase(broadphase);
+            }
+            build(state.as_mut());
+            state
+        },
+        None => crate::load_state(&args.scene_path, args.backend, args.broadphase)?,
+    };
+
+    let err = |e: std::io::Error| format!("{}: {}", args.out_path, e);
+    let mut recorder = Recorder::create(&args.out_path, args.every).map_err(err)?;
+
+    recorder.record(state.as_ref()).map_err(err)?;
+    for _ in 0..args.steps {
+        state.step(args.dt);
+        recorder.record(state.as_ref()).map_err(err)?;
+    }
+    recorder.flush().map_err(err)?;
+
+    Ok(())
+}
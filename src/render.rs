@@ -1,14 +1,18 @@
 use nalgebra::vector;
 use nalgebra as na;
+use std::collections::HashMap;
 use std::time::Instant;
 use gl_lib::{gl, helpers, objects::{sphere, cube}, shader::{self, Shader}, camera};
 use gl_lib::sdl2::keyboard::Keycode;
 use gl_lib::controller;
+use data_driven_simulation::simulation::trajectory::{Trajectory, Frame};
 
 
 use crate::*;
 
-pub fn run_with_render(mut state: sim::State) {
+/// With a trajectory the recorded run is played back instead of simulating live. State then only gives the walls,
+/// planes and sphere radii
+pub fn run_with_render(state: Box<dyn Simulation>, scene_path: Option<String>, backend: Option<Backend>, broadphase: Option<BroadphaseKind>, mut recorder: Option<Recorder>, trajectory: Option<Trajectory>) {
 
     // setup render
     let sdl_setup = helpers::setup_sdl().unwrap();
@@ -67,14 +71,21 @@ pub fn run_with_render(mut state: sim::State) {
     let mut event_pump = sdl.event_pump().unwrap();
     camera_controller.speed = 40.0;
 
-    let mut kb_map = setup_keyboard_mapping();
+    let mut kb_map = setup_keyboard_mapping(trajectory.is_some());
 
-    let mut kb_state = KbState { state, paused: false };
+    let replay = trajectory.map(|trajectory| Replay::new(trajectory, state.as_ref()));
+    let mut kb_state = KbState { state, paused: false, scene_path, backend, broadphase, replay };
+
+    if let Some(recorder) = &mut recorder {
+        record(recorder, kb_state.state.as_ref());
+    }
 
     let mut frame = 0;
     loop {
 
-        println!("{:?}", kb_state.state.spheres.positions);
+        if kb_state.replay.is_none() {
+            println!("{:?}", kb_state.state.sphere_positions());
+        }
         if frame > 100 {
 
             //break;
@@ -92,7 +103,15 @@ pub fn run_with_render(mut state: sim::State) {
 
 
 
-        if !kb_state.paused {
+        if let Some(replay) = &mut kb_state.replay {
+            if !kb_state.paused {
+                replay.advance();
+                // hold the last frame instead of looping
+                if replay.at_end() {
+                    kb_state.paused = true;
+                }
+            }
+        } else if !kb_state.paused {
 
            accumulator += delta * speed;
             /*
@@ -103,7 +122,11 @@ pub fn run_with_render(mut state: sim::State) {
             }
             */
             // simulate 1 step per frame, otherwise we might be too slow, if we cannot keep up
-            sim::step(&mut kb_state.state, sim_step_time);
+            kb_state.state.step(sim_step_time);
+
+            if let Some(recorder) = &mut recorder {
+                record(recorder, kb_state.state.as_ref());
+            }
         }
 
         // Rendering
@@ -123,8 +146,16 @@ pub fn run_with_render(mut state: sim::State) {
 
         };
 
-        render_spheres(&kb_state.state, &ri);
-        render_walls(&kb_state.state, &ri);
+        match &kb_state.replay {
+            Some(replay) => {
+                let frame = replay.frame();
+                let radius: Vec::<f32> = frame.ids.iter().map(|id| replay.radius(*id)).collect();
+                render_spheres(&frame.positions, &radius, &ri);
+            },
+            None => render_spheres(&kb_state.state.sphere_positions(), &kb_state.state.sphere_radius(), &ri),
+        }
+        render_walls(kb_state.state.as_ref(), &ri);
+        render_planes(kb_state.state.as_ref(), &ri);
 
         window.gl_swap_window();
 
@@ -135,6 +166,14 @@ pub fn run_with_render(mut state: sim::State) {
 }
 
 
+// flushed every step, escape exits the process without dropping the recorder
+fn record(recorder: &mut Recorder, state: &dyn Simulation) {
+    if let Err(err) = recorder.record(state).and_then(|_| recorder.flush()) {
+        println!("Could not record trajectory: {}", err);
+    }
+}
+
+
 struct RenderInfo<'a> {
     gl: &'a gl::Gl,
     sphere_shader: &'a shader::BaseShader,
@@ -145,18 +184,17 @@ struct RenderInfo<'a> {
     colors: &'a Vec::<sim::V3>
 }
 
-fn render_spheres(state: &sim::State, ri: &RenderInfo) {
+fn render_spheres(positions: &[sim::V3], radius: &[f32], ri: &RenderInfo) {
 
     ri.sphere_shader.set_mat4(ri.gl, "view", ri.camera.view());
     ri.sphere_shader.set_mat4(ri.gl, "projection", ri.camera.projection());
 
-    let radius = state.sphere_radius();
     let mut i = 0;
     // Render each Sphere
-    for pos in state.sphere_positions() {
+    for pos in positions {
 
         ri.sphere_shader.set_vec3(ri.gl, "color", ri.colors[i % 3]);
-        let model_mat =  na::Matrix4::new_translation(&pos);
+        let model_mat =  na::Matrix4::new_translation(pos);
         ri.sphere_shader.set_mat4(ri.gl, "model", model_mat);
         ri.sphere_shader.set_f32(ri.gl, "radius", radius[i]);
         ri.sphere.render(ri.gl);
@@ -164,7 +202,7 @@ fn render_spheres(state: &sim::State, ri: &RenderInfo) {
     }
 }
 
-fn render_walls(state: &sim::State, ri: &RenderInfo) {
+fn render_walls(state: &dyn Simulation, ri: &RenderInfo) {
 
     ri.cube_shader.set_mat4(ri.gl, "view", ri.camera.view());
     ri.cube_shader.set_mat4(ri.gl, "projection", ri.camera.projection());
@@ -172,10 +210,12 @@ fn render_walls(state: &sim::State, ri: &RenderInfo) {
     let mut i = 0;
     // Render each Sphere
 
-    for pos in &state.walls.positions {
+    let walls = state.walls();
+    for pos in walls.positions() {
         ri.cube_shader.set_vec3(ri.gl, "color", ri.colors[i % 3]);
         let mut model_mat = na::Matrix4::identity();
-        model_mat = model_mat.prepend_nonuniform_scaling(&state.walls.sizes[i]);
+        model_mat = model_mat.prepend_nonuniform_scaling(&walls.sizes()[i]);
+        model_mat = walls.rotations()[i].to_homogeneous() * model_mat;
         model_mat =model_mat.append_translation(pos);
         ri.cube_shader.set_mat4(ri.gl, "model", model_mat);
         ri.cube.render(ri.gl);
@@ -184,38 +224,204 @@ fn render_walls(state: &sim::State, ri: &RenderInfo) {
 
 }
 
+fn render_planes(state: &dyn Simulation, ri: &RenderInfo) {
+
+    ri.cube_shader.set_mat4(ri.gl, "view", ri.camera.view());
+    ri.cube_shader.set_mat4(ri.gl, "projection", ri.camera.projection());
+
+    // planes are infinite, draw them as a large thin cube
+    let size = na::Vector3::new(200.0, 0.05, 200.0);
+    let up = na::Vector3::y();
+
+    let planes = state.planes();
+    for i in 0..planes.count() {
+        let normal = planes.normals[i];
+        // rotation_between is None when normal is exactly -y
+        let rotation = na::UnitQuaternion::rotation_between(&up, &normal)
+            .unwrap_or_else(|| na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), std::f32::consts::PI));
+
+        ri.cube_shader.set_vec3(ri.gl, "color", na::Vector3::new(0.6, 0.6, 0.6));
+        let mut model_mat = na::Matrix4::identity();
+        model_mat = model_mat.prepend_nonuniform_scaling(&size);
+        model_mat = rotation.to_homogeneous() * model_mat;
+        model_mat = model_mat.append_translation(&planes.points[i]);
+        ri.cube_shader.set_mat4(ri.gl, "model", model_mat);
+        ri.cube.render(ri.gl);
+    }
+}
+
 
-fn setup_keyboard_mapping() -> controller::ControllerMapping<KbState> {
+fn setup_keyboard_mapping(replay: bool) -> controller::ControllerMapping<KbState> {
     let mut kb_map = controller::ControllerMapping::new();
 
     use Keycode::*;
     kb_map.exit(Keycode::Escape);
     kb_map.add_on_press(Keycode::R, reset);
     kb_map.add_on_press(P, pause);
-    kb_map.add_on_press(K, dump);
+
+    if replay {
+        kb_map.add_on_press(Space, pause);
+        kb_map.add_on_press(Left, step_back);
+        kb_map.add_on_press(Right, step_forward);
+        kb_map.add_on_press(Home, to_start);
+        kb_map.add_on_press(End, to_end);
+        kb_map.add_on_press(Up, faster);
+        kb_map.add_on_press(Down, slower);
+    } else {
+        kb_map.add_on_press(K, dump);
+        kb_map.add_on_press(L, load);
+    }
 
     kb_map
 }
 
 
 
+const DUMP_PATH: &str = "state.txt";
+
 struct KbState {
-    pub state: sim::State,
-    pub paused: bool
+    pub state: Box<dyn Simulation>,
+    pub paused: bool,
+    pub scene_path: Option<String>,
+    pub backend: Option<Backend>,
+    pub broadphase: Option<BroadphaseKind>,
+    // playing back a recorded run instead of simulating
+    pub replay: Option<Replay>
 }
 
 fn reset(state: &mut KbState) {
-    state.state = collision();
+    if let Some(replay) = &mut state.replay {
+        replay.seek(0);
+        return;
+    }
+
+    // reload scene file, so changes can be tried without restarting
+    match load_state(&state.scene_path, state.backend, state.broadphase) {
+        Ok(new_state) => state.state = new_state,
+        Err(err) => println!("{}", err)
+    }
 }
 
 fn pause(state: &mut KbState) {
     state.paused = !state.paused;
+
+    // playing again from the last frame starts over
+    if let Some(replay) = &mut state.replay {
+        if !state.paused && replay.at_end() {
+            replay.seek(0);
+        }
+    }
 }
 
 fn dump(state: &mut KbState) {
-    state.state.dump("state.txt");
+    if let Err(err) = state.state.dump(DUMP_PATH) {
+        println!("Could not dump state to {}: {}", DUMP_PATH, err);
+    }
+}
+
+fn load(state: &mut KbState) {
+    match state.state.backend().load(DUMP_PATH) {
+        Ok(mut new_state) => {
+            new_state.set_broadphase(state.state.broadphase());
+            state.state = new_state;
+        },
+        Err(err) => println!("Could not load state from {}: {}", DUMP_PATH, err)
+    }
+}
+
+
+
+
+const MIN_SPEED: f32 = 1.0 / 64.0;
+const MAX_SPEED: f32 = 64.0;
+
+struct Replay {
+    trajectory: Trajectory,
+    // index into the frames, fractional so speeds below one frame per rendered frame work
+    position: f32,
+    // recorded frames per rendered frame
+    speed: f32,
+    // radius by sphere id, from the scene. The trajectory only holds positions and velocities
+    radius: HashMap::<sim::EntityId, f32>,
+}
+
+impl Replay {
+    fn new(trajectory: Trajectory, scene: &dyn Simulation) -> Self {
+        let radius = scene.sphere_ids().into_iter().zip(scene.sphere_radius()).collect();
+        Self { trajectory, position: 0.0, speed: 1.0, radius }
+    }
+
+    fn last(&self) -> usize {
+        self.trajectory.frames.len().saturating_sub(1)
+    }
+
+    fn index(&self) -> usize {
+        (self.position as usize).min(self.last())
+    }
+
+    fn frame(&self) -> &Frame {
+        &self.trajectory.frames[self.index()]
+    }
+
+    // spheres that are not in the scene get a radius of 1
+    fn radius(&self, id: sim::EntityId) -> f32 {
+        self.radius.get(&id).copied().unwrap_or(1.0)
+    }
+
+    fn advance(&mut self) {
+        self.position = (self.position + self.speed).min(self.last() as f32);
+    }
+
+    fn at_end(&self) -> bool {
+        self.index() == self.last()
+    }
+
+    fn seek(&mut self, index: usize) {
+        self.position = index.min(self.last()) as f32;
+        println!("frame {}/{}, step {}", self.index(), self.last(), self.frame().step);
+    }
+}
+
+// scrubbing pauses, so the frame stays put
+fn step_back(state: &mut KbState) {
+    if let Some(replay) = &mut state.replay {
+        state.paused = true;
+        replay.seek(replay.index().saturating_sub(1));
+    }
 }
 
+fn step_forward(state: &mut KbState) {
+    if let Some(replay) = &mut state.replay {
+        state.paused = true;
+        replay.seek(replay.index() + 1);
+    }
+}
+
+fn to_start(state: &mut KbState) {
+    if let Some(replay) = &mut state.replay {
+        replay.seek(0);
+    }
+}
+
+fn to_end(state: &mut KbState) {
+    if let Some(replay) = &mut state.replay {
+        replay.seek(replay.last());
+    }
+}
+
+fn faster(state: &mut KbState) {
+    if let Some(replay) = &mut state.replay {
+        replay.speed = (replay.speed * 2.0).min(MAX_SPEED);
+        println!("replay speed {} frames per frame", replay.speed);
+    }
+}
+
+fn slower(state: &mut KbState) {
+    if let Some(replay) = &mut state.replay {
+        replay.speed = (replay.speed / 2.0).max(MIN_SPEED);
+        println!("replay speed {} frames per frame", replay.speed);
+    }
+}
 
 
 
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use nalgebra as na;
+use nalgebra::vector;
+
+use crate::simulation as sim;
+use sim::{V3, Simulation, Backend, BACKENDS};
+use sim::forces::ForceField;
+use sim::broadphase::{BroadphaseKind, BROADPHASES};
+use sim::material::{Material, MaterialId, CombineRule, DEFAULT_MATERIAL, WALL_MATERIAL};
+
+// Scene files are plain text, one entity per line. Empty lines and lines starting with # are ignored
+//
+// backend name=oop
+// broadphase name=quadtree scale=10
+// broadphase name=grid cell_size=2
+// material name=rubber restitution=0.9 static_friction=0.8 dynamic_friction=0.6 density=1.1
+// ball pos=30,0,0 vel=-200,0,0 spin=0,0,5 radius=1 mass=0.5 material=rubber
+// wall center=0,0,0 size=1,5,10 angle=30 axis=0,0,1 material=rubber
+// plane point=0,0,0 normal=0,1,0 one_sided=true material=rubber
+// world gravity=0,-9.81,0 restitution_rule=average friction_rule=average
+// uniform force=1,0,0
+// attractor center=0,0,0 strength=10 min_dist=1
+// drag coefficient=0.1
+// wind min=-5,-5,-5 max=5,5,5 velocity=2,0,0 coefficient=0.5
+//
+// vel and spin default to 0,0,0, angle to 0, axis to 0,0,1, one_sided to false and min_dist to 1 when omitted,
+// material defaults to the default material, or the wall material for walls, and a ball without mass gets it from the material density.
+// All other keys are required, except on world and material where everything but name is optional.
+// Wall angles are in degrees and wall sizes must be greater than 0. Materials must be defined before they are used, name=default and name=wall change the default materials.
+// world also takes static_friction and dynamic_friction from before materials, they set the friction of both default materials.
+// The backend is picked before anything is added, so its line can be anywhere in the file
+
+
+const KINDS: &[&str] = &["backend", "broadphase", "material", "ball", "wall", "plane", "world", "uniform", "attractor", "drag", "wind"];
+
+
+#[derive(Debug)]
+pub struct SceneError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.msg)
+        } else {
+            write!(f, "line {}: {}", self.line, self.msg)
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+
+/// backend overrides the backend given in the scene file, when neither is given the default backend is used
+pub fn load(path: &str, backend: Option<Backend>) -> Result<Box<dyn Simulation>, SceneError> {
+    let src = fs::read_to_string(path).map_err(|e| SceneError { line: 0, msg: format!("could not read '{}': {}", path, e) })?;
+    parse(&src, backend)
+}
+
+pub fn parse(src: &str, backend: Option<Backend>) -> Result<Box<dyn Simulation>, SceneError> {
+    let mut entries = vec![];
+    let mut scene_backend = None;
+
+    for (i, raw) in src.lines().enumerate() {
+        let line = i + 1;
+        let text = raw.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        let mut tokens = text.split_whitespace();
+        // Non empty line, so there is always a first token
+        let kind = tokens.next().unwrap();
+        let mut entry = Entry::parse(line, tokens)?;
+
+        if kind == "backend" {
+            if scene_backend.is_some() {
+                return Err(entry.err("backend is given more than once".to_string()));
+            }
+            let name = entry.required("name")?;
+            entry.finish()?;
+            scene_backend = Some(Backend::from_name(name).ok_or_else(|| {
+                let names : Vec::<&str> = BACKENDS.iter().map(|b| b.name()).collect();
+                entry.err(format!("unknown backend '{}', expected one of {}", name, names.join(", ")))
+            })?);
+        } else {
+            entries.push((kind, entry));
+        }
+    }
+
+    let mut state = backend.or(scene_backend).unwrap_or_default().new_state();
+
+    let mut materials : HashMap::<&str, MaterialId> = HashMap::new();
+    materials.insert("default", DEFAULT_MATERIAL);
+    materials.insert("wall", WALL_MATERIAL);
+
+    for (kind, mut entry) in entries {
+        match kind {
+            "broadphase" => {
+                let name = entry.required("name")?;
+                let mut broadphase = BroadphaseKind::from_name(name).ok_or_else(|| {
+                    let names : Vec::<&str> = BROADPHASES.iter().map(|b| b.name()).collect();
+                    entry.err(format!("unknown broadphase '{}', expected one of {}", name, names.join(", ")))
+                })?;
+
+                match &mut broadphase {
+                    BroadphaseKind::QuadTree { scale } => {
+                        *scale = entry.optional_positive("scale")?.unwrap_or(*scale);
+                    },
+                    BroadphaseKind::Grid { cell_size } => {
+                        *cell_size = entry.optional_positive("cell_size")?.unwrap_or(*cell_size);
+                    },
+                    _ => {}
+                }
+                entry.finish()?;
+                state.set_broadphase(broadphase);
+            },
+            "material" => {
+                let name = entry.required("name")?;
+                let default = Material::default();
+                let material = Material {
+                    restitution: entry.optional_non_negative("restitution")?.unwrap_or(default.restitution),
+                    static_friction: entry.optional_non_negative("static_friction")?.unwrap_or(default.static_friction),
+                    dynamic_friction: entry.optional_non_negative("dynamic_friction")?.unwrap_or(default.dynamic_friction),
+                    density: entry.optional_positive("density")?.unwrap_or(default.density),
+                };
+                entry.finish()?;
+
+                if name == "default" {
+                    state.materials_mut().materials[DEFAULT_MATERIAL] = material;
+                } else if name == "wall" {
+                    state.materials_mut().materials[WALL_MATERIAL] = material;
+                } else if materials.contains_key(name) {
+                    return Err(entry.err(format!("material '{}' is already defined", name)));
+                } else {
+                    materials.insert(name, state.add_material(material));
+                }
+            },
+            "ball" => {
+                let pos = entry.vector("pos")?;
+                let vel = entry.optional_vector("vel")?.unwrap_or(vector![0.0, 0.0, 0.0]);
+                let spin = entry.optional_vector("spin")?.unwrap_or(vector![0.0, 0.0, 0.0]);
+                let radius = entry.positive("radius")?;
+                let mass = entry.optional_positive("mass")?;
+                let material = entry.material(&materials, DEFAULT_MATERIAL)?;
+                entry.finish()?;
+                let mass = mass.unwrap_or_else(|| state.materials().get(material).sphere_mass(radius));
+                state.add_ball_with_material(pos, vel, spin, radius, mass, material);
+            },
+            "wall" => {
+                let center = entry.vector("center")?;
+                let size = entry.positive_vector("size")?;
+                let angle = entry.optional_number("angle")?.unwrap_or(0.0);
+                let axis = entry.optional_vector("axis")?.unwrap_or(vector![0.0, 0.0, 1.0]);
+                let axis = na::Unit::try_new(axis, f32::EPSILON).ok_or_else(|| entry.err("'axis' must not be 0,0,0".to_string()))?;
+                let material = entry.material(&materials, WALL_MATERIAL)?;
+                entry.finish()?;
+                state.add_wall_with_material(center, size, na::UnitQuaternion::from_axis_angle(&axis, angle.to_radians()), material);
+            },
+            "plane" => {
+                let point = entry.vector("point")?;
+                let normal = entry.vector("normal")?;
+                if normal.norm() == 0.0 {
+                    return Err(entry.err("'normal' must not be 0,0,0".to_string()));
+                }
+                let one_sided = entry.optional_bool("one_sided")?.unwrap_or(false);
+                let material = entry.material(&materials, DEFAULT_MATERIAL)?;
+                entry.finish()?;
+                state.add_plane_with_material(point, normal, one_sided, material);
+            },
+            "world" => {
+                let gravity = entry.optional_vector("gravity")?;
+                let restitution_rule = entry.optional_rule("restitution_rule")?;
+                let friction_rule = entry.optional_rule("friction_rule")?;
+                // from before materials, kept so older scenes load. They set the friction of both default materials, like the old global friction
+                let static_friction = entry.optional_non_negative("static_friction")?;
+                let dynamic_friction = entry.optional_non_negative("dynamic_friction")?;
+                entry.finish()?;
+
+                if let Some(gravity) = gravity {
+                    state.set_gravity(gravity);
+                }
+
+                for id in [DEFAULT_MATERIAL, WALL_MATERIAL] {
+                    let default = &mut state.materials_mut().materials[id];
+                    if let Some(friction) = static_friction {
+                        default.static_friction = friction;
+                    }
+                    if let Some(friction) = dynamic_friction {
+                        default.dynamic_friction = friction;
+                    }
+                }
+
+                if let Some(rule) = restitution_rule {
+                    state.materials_mut().restitution_rule = rule;
+                }
+
+                if let Some(rule) = friction_rule {
+                    state.materials_mut().friction_rule = rule;
+                }
+            },
+            "uniform" => {
+                let force = entry.vector("force")?;
+                entry.finish()?;
+                state.add_force_field(ForceField::Uniform { force });
+            },
+            "attractor" => {
+                let center = entry.vector("center")?;
+                let strength = entry.number("strength")?;
+                let min_dist = entry.optional_positive("min_dist")?.unwrap_or(1.0);
+                entry.finish()?;
+                state.add_force_field(ForceField::Attractor { center, strength, min_dist });
+            },
+            "drag" => {
+                let coefficient = entry.non_negative("coefficient")?;
+                entry.finish()?;
+                state.add_force_field(ForceField::Drag { coefficient });
+            },
+            "wind" => {
+                let min = entry.vector("min")?;
+                let max = entry.vector("max")?;
+                let velocity = entry.vector("velocity")?;
+                let coefficient = entry.non_negative("coefficient")?;
+                entry.finish()?;
+                state.add_force_field(ForceField::Wind { min, max, velocity, coefficient });
+            },
+            _ => {
+                return Err(entry.err(format!("unknown entry '{}', expected one of {}", kind, KINDS.join(", "))));
+            }
+        }
+    }
+
+    Ok(state)
+}
+
+
+// key=value pairs of a single line, keys are removed as they are read so leftovers can be reported
+struct Entry<'a> {
+    line: usize,
+    values: Vec::<(&'a str, &'a str)>,
+}
+
+impl<'a> Entry<'a> {
+
+    fn parse(line: usize, tokens: impl Iterator<Item = &'a str>) -> Result<Self, SceneError> {
+        let mut values = vec![];
+        for token in tokens {
+            match token.split_once('=') {
+                Some((key, value)) if !key.is_empty() => {
+                    if values.iter().any(|&(k, _)| k == key) {
+                        return Err(SceneError { line, msg: format!("'{}' is given more than once", key) });
+                    }
+                    values.push((key, value));
+                },
+                _ => {
+                    return Err(SceneError { line, msg: format!("expected key=value, found '{}'", token) });
+                }
+            }
+        }
+
+        Ok(Self { line, values })
+    }
+
+    fn err(&self, msg: String) -> SceneError {
+        SceneError { line: self.line, msg }
+    }
+
+    fn take(&mut self, key: &str) -> Option<&'a str> {
+        let i = self.values.iter().position(|&(k, _)| k == key)?;
+        Some(self.values.remove(i).1)
+    }
+
+    fn required(&mut self, key: &str) -> Result<&'a str, SceneError> {
+        self.take(key).ok_or_else(|| self.err(format!("missing '{}'", key)))
+    }
+
+    fn parse_f32(&self, key: &str, s: &str) -> Result<f32, SceneError> {
+        match s.trim().parse::<f32>() {
+            Ok(v) if v.is_finite() => Ok(v),
+            _ => Err(self.err(format!("'{}' expects a number, found '{}'", key, s)))
+        }
+    }
+
+    fn parse_vector(&self, key: &str, s: &str) -> Result<V3, SceneError> {
+        let parts : Vec::<&str> = s.split(',').collect();
+        if parts.len() != 3 {
+            return Err(self.err(format!("'{}' expects x,y,z, found '{}'", key, s)));
+        }
+
+        Ok(vector![self.parse_f32(key, parts[0])?,
+                   self.parse_f32(key, parts[1])?,
+                   self.parse_f32(key, parts[2])?])
+    }
+
+    fn vector(&mut self, key: &str) -> Result<V3, SceneError> {
+        let s = self.required(key)?;
+        self.parse_vector(key, s)
+    }
+
+    fn positive_vector(&mut self, key: &str) -> Result<V3, SceneError> {
+        let v = self.vector(key)?;
+        if v.iter().any(|&c| c <= 0.0) {
+            return Err(self.err(format!("'{}' must be greater than 0 in every component, found {},{},{}", key, v.x, v.y, v.z)));
+        }
+        Ok(v)
+    }
+
+    fn optional_vector(&mut self, key: &str) -> Result<Option<V3>, SceneError> {
+        match self.take(key) {
+            Some(s) => self.parse_vector(key, s).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn optional_bool(&mut self, key: &str) -> Result<Option<bool>, SceneError> {
+        match self.take(key) {
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(s) => Err(self.err(format!("'{}' expects true or false, found '{}'", key, s))),
+            None => Ok(None)
+        }
+    }
+
+    fn number(&mut self, key: &str) -> Result<f32, SceneError> {
+        let s = self.required(key)?;
+        self.parse_f32(key, s)
+    }
+
+    fn optional_number(&mut self, key: &str) -> Result<Option<f32>, SceneError> {
+        match self.take(key) {
+            Some(s) => self.parse_f32(key, s).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn positive(&mut self, key: &str) -> Result<f32, SceneError> {
+        let v = self.number(key)?;
+        if v <= 0.0 {
+            return Err(self.err(format!("'{}' must be greater than 0, found {}", key, v)));
+        }
+        Ok(v)
+    }
+
+    fn optional_positive(&mut self, key: &str) -> Result<Option<f32>, SceneError> {
+        if self.values.iter().any(|&(k, _)| k == key) {
+            self.positive(key).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn non_negative(&mut self, key: &str) -> Result<f32, SceneError> {
+        let v = self.number(key)?;
+        if v < 0.0 {
+            return Err(self.err(format!("'{}' must not be negative, found {}", key, v)));
+        }
+        Ok(v)
+    }
+
+    fn optional_non_negative(&mut self, key: &str) -> Result<Option<f32>, SceneError> {
+        if self.values.iter().any(|&(k, _)| k == key) {
+            self.non_negative(key).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn optional_rule(&mut self, key: &str) -> Result<Option<CombineRule>, SceneError> {
+        match self.take(key) {
+            Some(s) => CombineRule::from_name(s).map(Some)
+                .ok_or_else(|| self.err(format!("'{}' expects min, max, average or multiply, found '{}'", key, s))),
+            None => Ok(None)
+        }
+    }
+
+    fn material(&mut self, materials: &HashMap::<&str, MaterialId>, default: MaterialId) -> Result<MaterialId, SceneError> {
+        match self.take("material") {
+            Some(name) => materials.get(name).copied().ok_or_else(|| self.err(format!("unknown material '{}'", name))),
+            None => Ok(default)
+        }
+    }
+
+    fn finish(&self) -> Result<(), SceneError> {
+        match self.values.first() {
+            Some((key, _)) => Err(self.err(format!("unknown key '{}'", key))),
+            None => Ok(())
+        }
+    }
+}
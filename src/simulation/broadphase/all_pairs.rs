@@ -0,0 +1,39 @@
+use super::{Broadphase, BroadphaseKind, V3, overlaps};
+
+// Tests every pair, O(n²). No state to keep up to date, so it is the reference the others are compared to
+
+
+#[derive(Debug)]
+pub struct AllPairs {}
+
+impl Default for AllPairs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AllPairs {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Broadphase for AllPairs {
+
+    fn kind(&self) -> BroadphaseKind {
+        BroadphaseKind::AllPairs
+    }
+
+    fn pairs(&mut self, positions: &[V3], radius: &[f32], pairs: &mut Vec::<(usize, usize)>) {
+        pairs.clear();
+        let count = positions.len();
+
+        for i in 0..count {
+            for j in (i + 1)..count {
+                if overlaps(positions, radius, i, j) {
+                    pairs.push((i, j));
+                }
+            }
+        }
+    }
+}
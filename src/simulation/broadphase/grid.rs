@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+use super::{Broadphase, BroadphaseKind, V3, overlaps};
+
+// Uniform grid of cubic cells stored in a hash map, so it has no bounds. A sphere is in every cell its box touches.
+// Kept between steps, only spheres that moved to other cells are updated.
+// Works best when spheres are about the same size and the cell size is close to their diameter.
+// Spheres covering more than MAX_CELLS cells are kept out of the grid and tested against every sphere,
+// like the large spheres in the octree root, so one huge sphere can't fill the map with cells
+
+
+type Cell = (i32, i32, i32);
+
+// Emptied cells keep their allocation, since spheres move back and forth between the same cells.
+// They are dropped when there are this many times more cells than in use
+const EMPTY_CELLS_FACTOR: usize = 4;
+
+const MAX_CELLS: i64 = 64;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Range {
+    // position is inf or NaN, in no cell
+    NotFinite,
+    Cells(Cell, Cell),
+    // covers more than MAX_CELLS cells
+    Oversized,
+}
+
+#[derive(Debug)]
+pub struct HashGrid {
+    cell_size: f32,
+    cells: HashMap::<Cell, Vec::<usize>, BuildHasherDefault::<CellHasher>>,
+    used_cells: usize,
+    ranges: Vec::<Range>, // cells covered by each sphere
+    oversized: Vec::<usize>, // sorted
+}
+
+impl HashGrid {
+
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::default(),
+            used_cells: 0,
+            ranges: vec![],
+            oversized: vec![],
+        }
+    }
+
+    fn cell(&self, pos: &V3) -> Cell {
+        ((pos.x / self.cell_size).floor() as i32,
+         (pos.y / self.cell_size).floor() as i32,
+         (pos.z / self.cell_size).floor() as i32)
+    }
+
+    fn range(&self, pos: &V3, radius: f32) -> Range {
+        if !pos.iter().all(|v| v.is_finite()) {
+            return Range::NotFinite;
+        }
+        let r = V3::new(radius, radius, radius);
+        let (min, max) = (self.cell(&(pos - r)), self.cell(&(pos + r)));
+
+        // a NaN radius gives cell 0, so the sphere is in one cell and overlaps nothing
+        // saturating, cells of huge spheres are clamped to the i32 range and the product overflows i64
+        let cells = (max.0 as i64 - min.0 as i64 + 1)
+            .saturating_mul(max.1 as i64 - min.1 as i64 + 1)
+            .saturating_mul(max.2 as i64 - min.2 as i64 + 1);
+        if cells > MAX_CELLS {
+            Range::Oversized
+        } else {
+            Range::Cells(min, max)
+        }
+    }
+
+    fn add(&mut self, i: usize, (min, max): (Cell, Cell)) {
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    let elements = self.cells.entry((x, y, z)).or_default();
+                    if elements.is_empty() {
+                        self.used_cells += 1;
+                    }
+                    elements.push(i);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, i: usize, (min, max): (Cell, Cell)) {
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    let elements = self.cells.get_mut(&(x, y, z)).unwrap();
+                    let k = elements.iter().position(|&e| e == i).unwrap();
+                    elements.swap_remove(k);
+                    if elements.is_empty() {
+                        self.used_cells -= 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Broadphase for HashGrid {
+
+    fn kind(&self) -> BroadphaseKind {
+        BroadphaseKind::Grid { cell_size: self.cell_size }
+    }
+
+    fn update(&mut self, positions: &[V3], radius: &[f32]) {
+        // spheres are never removed, so a shorter list is a different state, start over
+        if positions.len() < self.ranges.len() {
+            self.cells.clear();
+            self.used_cells = 0;
+            self.ranges.clear();
+        }
+
+        for i in 0..positions.len() {
+            let new = self.range(&positions[i], radius[i]);
+
+            if i == self.ranges.len() {
+                self.ranges.push(Range::NotFinite);
+            } else if self.ranges[i] == new {
+                continue;
+            }
+
+            if let Range::Cells(min, max) = self.ranges[i] {
+                self.remove(i, (min, max));
+            }
+
+            if let Range::Cells(min, max) = new {
+                self.add(i, (min, max));
+            }
+            self.ranges[i] = new;
+        }
+
+        self.oversized.clear();
+        self.oversized.extend((0..self.ranges.len()).filter(|&i| self.ranges[i] == Range::Oversized));
+
+        if self.cells.len() > EMPTY_CELLS_FACTOR * self.used_cells.max(1) {
+            self.cells.retain(|_, elements| !elements.is_empty());
+        }
+    }
+
+    fn pairs(&mut self, positions: &[V3], radius: &[f32], pairs: &mut Vec::<(usize, usize)>) {
+        pairs.clear();
+
+        for i in 0..positions.len() {
+            let start = pairs.len();
+
+            let (min, max) = match self.ranges[i] {
+                Range::Cells(min, max) => (min, max),
+                Range::Oversized => {
+                    for j in (i + 1)..positions.len() {
+                        if overlaps(positions, radius, i, j) {
+                            pairs.push((i, j));
+                        }
+                    }
+                    continue;
+                },
+                // an infinite sphere can still overlap an oversized one with infinite radius
+                Range::NotFinite => ((0, 0, 0), (-1, -1, -1)),
+            };
+
+            // oversized spheres are in no cell, test them directly
+            let first = self.oversized.partition_point(|&j| j <= i);
+            for &j in &self.oversized[first..] {
+                if overlaps(positions, radius, i, j) {
+                    pairs.push((i, j));
+                }
+            }
+
+            // empty for spheres without cells
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    for z in min.2..=max.2 {
+                        for &j in &self.cells[&(x, y, z)] {
+                            if j <= i {
+                                continue;
+                            }
+
+                            // a pair can share several cells, only take it in the first one they share
+                            let other = match self.ranges[j] {
+                                Range::Cells(other, _) => other,
+                                _ => unreachable!("only spheres with cells are in the grid"),
+                            };
+                            if (x, y, z) != (min.0.max(other.0), min.1.max(other.1), min.2.max(other.2)) {
+                                continue;
+                            }
+
+                            if overlaps(positions, radius, i, j) {
+                                pairs.push((i, j));
+                            }
+                        }
+                    }
+                }
+            }
+            pairs[start..].sort_unstable();
+        }
+    }
+}
+
+
+// The default hasher is made to resist collision attacks, cell keys are just a few integers so a multiply is enough
+#[derive(Default)]
+struct CellHasher(u64);
+
+impl Hasher for CellHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0.rotate_left(5) ^ b as u64).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+        }
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.0 = (self.0.rotate_left(5) ^ i as u32 as u64).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+use super::V3;
+
+mod all_pairs;
+mod quadtree;
+mod octree;
+mod grid;
+mod sweep_and_prune;
+mod walls;
+
+pub use all_pairs::AllPairs;
+pub use self::quadtree::QuadTreeBroadphase;
+pub use octree::Octree;
+pub use grid::HashGrid;
+pub use sweep_and_prune::SweepAndPrune;
+pub use walls::WallIndex;
+
+// Broadphase finds the sphere pairs that might touch, so the narrowphase does not have to test all of them.
+// Both backends use the same implementations, picked at runtime.
+
+
+pub trait Broadphase: fmt::Debug {
+
+    fn kind(&self) -> BroadphaseKind;
+
+    /// Bring the structure up to date with the spheres at positions with radius, called every step before pairs.
+    /// Kept apart from pairs so the tree rebuild can be timed on its own
+    fn update(&mut self, _positions: &[V3], _radius: &[f32]) {}
+
+    /// Fill pairs with the candidate pairs (i, j), i < j, for the spheres given to the last update.
+    /// Every pair whose bounding boxes overlap must be included, and pairs are sorted and unique,
+    /// so the narrowphase visits them in the same order whichever broadphase is used
+    fn pairs(&mut self, positions: &[V3], radius: &[f32], pairs: &mut Vec::<(usize, usize)>);
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BroadphaseKind {
+    AllPairs,
+    /// scale is tree cells per world unit
+    QuadTree { scale: f32 },
+    Octree,
+    /// cell_size is the side of the cubic cells in world units
+    Grid { cell_size: f32 },
+    SweepAndPrune,
+}
+
+pub const DEFAULT_QUADTREE_SCALE: f32 = 1.0;
+pub const DEFAULT_GRID_CELL_SIZE: f32 = 2.0;
+
+/// Every broadphase with default settings
+pub const BROADPHASES: &[BroadphaseKind] = &[
+    BroadphaseKind::AllPairs,
+    BroadphaseKind::QuadTree { scale: DEFAULT_QUADTREE_SCALE },
+    BroadphaseKind::Octree,
+    BroadphaseKind::Grid { cell_size: DEFAULT_GRID_CELL_SIZE },
+    BroadphaseKind::SweepAndPrune,
+];
+
+impl BroadphaseKind {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BroadphaseKind::AllPairs => "all_pairs",
+            BroadphaseKind::QuadTree { .. } => "quadtree",
+            BroadphaseKind::Octree => "octree",
+            BroadphaseKind::Grid { .. } => "grid",
+            BroadphaseKind::SweepAndPrune => "sweep_and_prune",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        BROADPHASES.iter().copied().find(|b| b.name() == name)
+    }
+
+    pub fn create(&self) -> Box<dyn Broadphase> {
+        match self {
+            BroadphaseKind::AllPairs => Box::new(AllPairs::new()),
+            BroadphaseKind::QuadTree { scale } => Box::new(QuadTreeBroadphase::new(*scale)),
+            BroadphaseKind::Octree => Box::new(Octree::new()),
+            BroadphaseKind::Grid { cell_size } => Box::new(HashGrid::new(*cell_size)),
+            BroadphaseKind::SweepAndPrune => Box::new(SweepAndPrune::new()),
+        }
+    }
+}
+
+impl fmt::Display for BroadphaseKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+
+/// Axis aligned bounding boxes of sphere i and j overlap
+pub fn overlaps(positions: &[V3], radius: &[f32], i: usize, j: usize) -> bool {
+    let d = (positions[i] - positions[j]).abs();
+    let r = radius[i] + radius[j];
+    d.x <= r && d.y <= r && d.z <= r
+}
@@ -0,0 +1,193 @@
+use nalgebra::vector;
+
+use super::{Broadphase, BroadphaseKind, V3, overlaps};
+
+// Loose octree over the full 3D bounds of the spheres, rebuilt every step around the spheres' bounding box.
+// A sphere goes in the node containing its center, as deep as its radius fits in half the node size.
+// Node bounds are loosened by half their size, so every sphere is inside the loose bounds of its node
+// and a query only has to visit nodes whose loose bounds overlap the query box
+
+
+const ELEMENTS_PER_NODE: usize = 6;
+const MAX_DEPTH: usize = 16;
+
+
+#[derive(Debug)]
+struct Node {
+    center: V3,
+    half_size: f32, // nodes are cubes, loose bounds are center ± 2 * half_size
+    depth: usize,
+    children: Option<usize>, // index of the first of 8 consecutive child nodes
+    elements: Vec::<usize>,
+}
+
+impl Node {
+    fn new(center: V3, half_size: f32, depth: usize) -> Self {
+        Self { center, half_size, depth, children: None, elements: vec![] }
+    }
+
+    fn octant(&self, pos: &V3) -> usize {
+        let mut index = 0;
+        for axis in 0..3 {
+            if pos[axis] >= self.center[axis] {
+                index |= 1 << axis;
+            }
+        }
+        index
+    }
+
+    fn loose_overlaps(&self, min: &V3, max: &V3) -> bool {
+        let loose = 2.0 * self.half_size;
+        (0..3).all(|axis| min[axis] <= self.center[axis] + loose && max[axis] >= self.center[axis] - loose)
+    }
+}
+
+
+// sphere i with its bounding box, looked up against the tree
+struct Query<'a> {
+    i: usize,
+    min: V3,
+    max: V3,
+    positions: &'a [V3],
+    radius: &'a [f32],
+}
+
+
+#[derive(Debug)]
+pub struct Octree {
+    nodes: Vec::<Node>,
+}
+
+impl Default for Octree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Octree {
+
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![],
+        }
+    }
+
+    fn build(&mut self, positions: &[V3], radius: &[f32]) {
+        self.nodes.clear();
+
+        if positions.is_empty() {
+            return;
+        }
+
+        // a sphere that has blown up to inf or NaN would make every node bound NaN, leave it out of the bounds
+        let mut min = vector![f32::MAX, f32::MAX, f32::MAX];
+        let mut max = vector![f32::MIN, f32::MIN, f32::MIN];
+        for pos in positions.iter().filter(|p| p.iter().all(|v| v.is_finite())) {
+            min = min.inf(pos);
+            max = max.sup(pos);
+        }
+
+        if min.x > max.x {
+            min = vector![0.0, 0.0, 0.0];
+            max = min;
+        }
+
+        let center = (min + max) / 2.0;
+        let half_size = ((max - min) / 2.0).max();
+        self.nodes.push(Node::new(center, half_size, 0));
+
+        for i in 0..positions.len() {
+            self.insert(i, positions, radius);
+        }
+    }
+
+    fn insert(&mut self, i: usize, positions: &[V3], radius: &[f32]) {
+        let mut node = 0;
+
+        while let Some(children) = self.nodes[node].children {
+            // too big for the children, stays here
+            if radius[i] > self.nodes[node].half_size / 2.0 {
+                break;
+            }
+            node = children + self.nodes[node].octant(&positions[i]);
+        }
+
+        self.nodes[node].elements.push(i);
+
+        if self.nodes[node].children.is_none()
+            && self.nodes[node].elements.len() > ELEMENTS_PER_NODE
+            && self.nodes[node].depth < MAX_DEPTH {
+            self.split(node, positions, radius);
+        }
+    }
+
+    fn split(&mut self, node: usize, positions: &[V3], radius: &[f32]) {
+        let center = self.nodes[node].center;
+        let half = self.nodes[node].half_size / 2.0;
+        let depth = self.nodes[node].depth + 1;
+
+        let first = self.nodes.len();
+        for c in 0..8 {
+            let offset = vector![
+                if c & 1 != 0 { half } else { -half },
+                if c & 2 != 0 { half } else { -half },
+                if c & 4 != 0 { half } else { -half }];
+            self.nodes.push(Node::new(center + offset, half, depth));
+        }
+        self.nodes[node].children = Some(first);
+
+        // push down the elements that fit in a child, the rest stay
+        let elements = std::mem::take(&mut self.nodes[node].elements);
+        for e in elements {
+            if radius[e] > half {
+                self.nodes[node].elements.push(e);
+            } else {
+                let c = self.nodes[node].octant(&positions[e]);
+                self.nodes[first + c].elements.push(e);
+            }
+        }
+    }
+
+    // add (q.i, j) for every j > q.i in node and its children whose box overlaps sphere q.i
+    fn query(&self, node: usize, q: &Query, pairs: &mut Vec::<(usize, usize)>) {
+        let n = &self.nodes[node];
+        if !n.loose_overlaps(&q.min, &q.max) {
+            return;
+        }
+
+        for &j in &n.elements {
+            if j > q.i && overlaps(q.positions, q.radius, q.i, j) {
+                pairs.push((q.i, j));
+            }
+        }
+
+        if let Some(children) = n.children {
+            for c in 0..8 {
+                self.query(children + c, q, pairs);
+            }
+        }
+    }
+}
+
+impl Broadphase for Octree {
+
+    fn kind(&self) -> BroadphaseKind {
+        BroadphaseKind::Octree
+    }
+
+    fn update(&mut self, positions: &[V3], radius: &[f32]) {
+        self.build(positions, radius);
+    }
+
+    fn pairs(&mut self, positions: &[V3], radius: &[f32], pairs: &mut Vec::<(usize, usize)>) {
+        pairs.clear();
+
+        for i in 0..positions.len() {
+            let start = pairs.len();
+            let r = vector![radius[i], radius[i], radius[i]];
+            let query = Query { i, min: positions[i] - r, max: positions[i] + r, positions, radius };
+            self.query(0, &query, pairs);
+            pairs[start..].sort_unstable();
+        }
+    }
+}
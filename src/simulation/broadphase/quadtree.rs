@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use quadtree::{QuadTree, Rect, Point};
+
+use super::{Broadphase, BroadphaseKind, V3, overlaps};
+
+// 2D quadtree over x and y. The tree holds each sphere's rect grown by FAT_MARGIN cells, and a sphere
+// is only moved in the tree when its rect leaves that, so most steps only a few spheres are touched.
+// The tree covers -extent..extent, when a sphere leaves it the extent is doubled until everything fits
+// and the tree is built again, so there is no fixed world size.
+// The tree works in integers, positions are multiplied by scale first. Bounds are always rounded outwards,
+// so nothing is missed, and a larger scale makes the cells fit spheres smaller than one unit
+
+
+const INITIAL_EXTENT: i32 = 128;
+const FAT_MARGIN: i32 = 1;
+
+// min x, min y, max x, max y in tree coordinates
+type Bounds = [i32; 4];
+
+
+#[derive(Debug)]
+pub struct QuadTreeBroadphase {
+    scale: f32,
+    extent: i32,
+    qt_id_to_index : HashMap::<i32, usize>,
+    qt_ids: Vec::<i32>, // tree element of each sphere
+    fat_bounds: Vec::<Bounds>, // bounds each sphere is stored with in the tree
+    tree: QuadTree::<usize>,
+    query_res: Vec::<i32>,
+    ids: Vec::<usize>,
+}
+
+impl QuadTreeBroadphase {
+
+    pub fn new(scale: f32) -> Self {
+        Self {
+            scale,
+            extent: INITIAL_EXTENT,
+            qt_id_to_index : HashMap::new(),
+            qt_ids: vec![],
+            fat_bounds: vec![],
+            tree: new_tree(INITIAL_EXTENT),
+            query_res: vec![],
+            ids: vec![],
+        }
+    }
+
+    // bounding rect in tree coordinates, rounded outwards
+    fn bounds(&self, pos: &V3, radius: f32) -> Bounds {
+        let min = (pos - V3::new(radius, radius, radius)) * self.scale;
+        let max = (pos + V3::new(radius, radius, radius)) * self.scale;
+        [min.x.floor() as i32, min.y.floor() as i32, max.x.ceil() as i32, max.y.ceil() as i32]
+    }
+
+    // (re)insert sphere i with bounds grown by the margin
+    fn insert(&mut self, i: usize, bounds: &Bounds) {
+        let fat = [bounds[0].saturating_sub(FAT_MARGIN), bounds[1].saturating_sub(FAT_MARGIN),
+                   bounds[2].saturating_add(FAT_MARGIN), bounds[3].saturating_add(FAT_MARGIN)];
+
+        let element_id = self.tree.insert(i, rect(&fat));
+        self.qt_id_to_index.insert(element_id, i);
+
+        if i < self.qt_ids.len() {
+            self.qt_ids[i] = element_id;
+            self.fat_bounds[i] = fat;
+        } else {
+            self.qt_ids.push(element_id);
+            self.fat_bounds.push(fat);
+        }
+    }
+
+    // smallest doubling of the current extent that contains every sphere
+    fn required_extent(&self, positions: &[V3], radius: &[f32]) -> i32 {
+        let mut needed : f32 = 0.0;
+        for (pos, r) in positions.iter().zip(radius) {
+            needed = needed.max(pos.x.abs() + r).max(pos.y.abs() + r);
+        }
+        needed = needed * self.scale + FAT_MARGIN as f32;
+
+        let mut extent = self.extent;
+        while (extent as f32) < needed {
+            match extent.checked_mul(2) {
+                Some(e) => extent = e,
+                // far outside anything the integer tree can hold, keep the largest
+                None => break
+            }
+        }
+        extent
+    }
+
+    // move spheres that left their fat bounds, new spheres are inserted
+    fn order_tree(&mut self, positions: &[V3], radius: &[f32]) {
+
+        let extent = self.required_extent(positions, radius);
+        if extent != self.extent {
+            self.extent = extent;
+            self.tree = new_tree(extent);
+            self.qt_id_to_index.clear();
+            self.qt_ids.clear();
+            self.fat_bounds.clear();
+        }
+
+        let mut removed = false;
+        for i in 0..positions.len() {
+            let bounds = self.bounds(&positions[i], radius[i]);
+
+            if i < self.qt_ids.len() {
+                let fat = &self.fat_bounds[i];
+                if fat[0] <= bounds[0] && fat[1] <= bounds[1] && bounds[2] <= fat[2] && bounds[3] <= fat[3] {
+                    continue;
+                }
+
+                let qt_id = self.qt_ids[i];
+                self.tree.remove(qt_id);
+                self.qt_id_to_index.remove(&qt_id);
+                removed = true;
+            }
+
+            self.insert(i, &bounds);
+        }
+
+        if removed {
+            self.tree.cleanup();
+        }
+    }
+
+    fn query_points(&mut self, pos: &V3, radius: f32) {
+        self.query_res.clear();
+        self.ids.clear();
+
+        let r = rect(&self.bounds(pos, radius));
+
+        // use -1 as omit elemnet since getting it is more work than just not processing, since we use j<=i continue
+        self.tree.query(r, -1, &mut self.query_res);
+
+        for q_id in &self.query_res {
+            self.ids.push(*self.qt_id_to_index.get(q_id).unwrap())
+        }
+
+        self.ids.sort();
+        self.ids.dedup();
+    }
+}
+
+fn rect(bounds: &Bounds) -> Rect {
+    Rect::from_points(Point { x: bounds[0], y: bounds[1] }, Point { x: bounds[2], y: bounds[3] })
+}
+
+fn new_tree(extent: i32) -> QuadTree::<usize> {
+    let mut qt = QuadTree::new(Rect::from_points(Point {x: -extent, y: -extent}, Point { x: extent, y: extent}));
+    qt.set_elements_per_node(6);
+    qt
+}
+
+
+impl Broadphase for QuadTreeBroadphase {
+
+    fn kind(&self) -> BroadphaseKind {
+        BroadphaseKind::QuadTree { scale: self.scale }
+    }
+
+    fn update(&mut self, positions: &[V3], radius: &[f32]) {
+        // spheres are never removed, so a shorter list is a different state, start over
+        if positions.len() < self.qt_ids.len() {
+            *self = Self::new(self.scale);
+        }
+
+        self.order_tree(positions, radius);
+    }
+
+    fn pairs(&mut self, positions: &[V3], radius: &[f32], pairs: &mut Vec::<(usize, usize)>) {
+        pairs.clear();
+
+        for i in 0..positions.len() {
+            self.query_points(&positions[i], radius[i]);
+            for &j in &self.ids {
+                // the tree only knows x and y in whole cells, check the exact boxes
+                if j <= i || !overlaps(positions, radius, i, j) {
+                    continue;
+                }
+
+                pairs.push((i, j));
+            }
+        }
+    }
+}
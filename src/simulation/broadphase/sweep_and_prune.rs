@@ -0,0 +1,95 @@
+use std::cmp::Ordering;
+
+use super::{Broadphase, BroadphaseKind, V3, overlaps};
+
+// Sort and sweep along x. Spheres are sorted by the start of their box, then each sphere is only tested
+// against the following spheres that start before it ends. The order is kept between steps and
+// spheres move little per step, so it is nearly sorted already and insertion sort is close to O(n)
+
+
+#[derive(Debug)]
+pub struct SweepAndPrune {
+    order: Vec::<usize>, // sphere indices sorted by min
+    min: Vec::<f32>,
+    max: Vec::<f32>,
+}
+
+impl Default for SweepAndPrune {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SweepAndPrune {
+
+    pub fn new() -> Self {
+        Self {
+            order: vec![],
+            min: vec![],
+            max: vec![],
+        }
+    }
+
+    fn sort(&mut self, count: usize) {
+        let order = &mut self.order;
+        let min = &self.min;
+
+        // spheres are never removed, so a shorter list is a different state, start over
+        if count < order.len() {
+            order.clear();
+        }
+
+        // new spheres can go anywhere, a full sort is cheaper than insertion sort on them
+        if order.len() < count {
+            order.extend(order.len()..count);
+            order.sort_by(|&a, &b| min[a].total_cmp(&min[b]));
+            return;
+        }
+
+        for k in 1..order.len() {
+            let e = order[k];
+            let mut m = k;
+            while m > 0 && min[order[m - 1]].total_cmp(&min[e]) == Ordering::Greater {
+                order[m] = order[m - 1];
+                m -= 1;
+            }
+            order[m] = e;
+        }
+    }
+}
+
+impl Broadphase for SweepAndPrune {
+
+    fn kind(&self) -> BroadphaseKind {
+        BroadphaseKind::SweepAndPrune
+    }
+
+    fn update(&mut self, positions: &[V3], radius: &[f32]) {
+        self.min.clear();
+        self.max.clear();
+        for (pos, r) in positions.iter().zip(radius) {
+            self.min.push(pos.x - r);
+            self.max.push(pos.x + r);
+        }
+
+        self.sort(positions.len());
+    }
+
+    fn pairs(&mut self, positions: &[V3], radius: &[f32], pairs: &mut Vec::<(usize, usize)>) {
+        pairs.clear();
+
+        for (k, &i) in self.order.iter().enumerate() {
+            for &j in &self.order[k + 1..] {
+                if self.min[j] > self.max[i] {
+                    break;
+                }
+
+                if overlaps(positions, radius, i, j) {
+                    pairs.push((i.min(j), i.max(j)));
+                }
+            }
+        }
+
+        pairs.sort_unstable();
+    }
+}
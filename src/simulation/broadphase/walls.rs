@@ -0,0 +1,140 @@
+use super::V3;
+use crate::simulation::colliders::Walls;
+
+// Bounding volume hierarchy over the walls' bounding boxes, so each sphere is only tested against walls near it.
+// Built again whenever the walls' generation changes, that is when a wall is added, moved, resized or rotated
+
+
+const WALLS_PER_LEAF: usize = 4;
+
+
+#[derive(Debug)]
+struct Node {
+    min: V3,
+    max: V3,
+    start: usize, // walls in order[start..end]
+    end: usize,
+    children: Option<(usize, usize)>,
+}
+
+
+#[derive(Debug)]
+pub struct WallIndex {
+    generation: Option<u64>, // of the walls it was built from
+    count: usize,
+    bounds: Vec::<(V3, V3)>,
+    order: Vec::<usize>,
+    nodes: Vec::<Node>,
+}
+
+impl Default for WallIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WallIndex {
+
+    pub fn new() -> Self {
+        Self {
+            generation: None,
+            count: 0,
+            bounds: vec![],
+            order: vec![],
+            nodes: vec![],
+        }
+    }
+
+    pub fn update(&mut self, walls: &Walls) {
+        if self.generation == Some(walls.generation()) {
+            return;
+        }
+
+        self.generation = Some(walls.generation());
+        self.count = walls.count();
+        self.bounds.clear();
+        self.nodes.clear();
+
+        for w_i in 0..walls.count() {
+            // half size of the rotated box along each world axis
+            let rotation = walls.rotations()[w_i].to_rotation_matrix();
+            let extent = rotation.matrix().abs() * (walls.sizes()[w_i] / 2.0);
+            let center = walls.positions()[w_i];
+            self.bounds.push((center - extent, center + extent));
+        }
+
+        self.order = (0..self.count).collect();
+        if self.count > 0 {
+            self.build(0, self.count);
+        }
+    }
+
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let mut min = self.bounds[self.order[start]].0;
+        let mut max = self.bounds[self.order[start]].1;
+        for &w in &self.order[start..end] {
+            min = min.inf(&self.bounds[w].0);
+            max = max.sup(&self.bounds[w].1);
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(Node { min, max, start, end, children: None });
+
+        if end - start <= WALLS_PER_LEAF {
+            return index;
+        }
+
+        // split at the median along the longest axis
+        let axis = (max - min).imax();
+        let bounds = &self.bounds;
+        self.order[start..end].sort_by(|&a, &b| {
+            let ca = bounds[a].0[axis] + bounds[a].1[axis];
+            let cb = bounds[b].0[axis] + bounds[b].1[axis];
+            ca.total_cmp(&cb)
+        });
+
+        let mid = (start + end) / 2;
+        let left = self.build(start, mid);
+        let right = self.build(mid, end);
+        self.nodes[index].children = Some((left, right));
+
+        index
+    }
+
+    /// Walls whose bounding box overlaps min..max, in ascending order so walls are resolved in the same order as testing all of them
+    pub fn query(&self, min: &V3, max: &V3, walls: &mut Vec::<usize>) {
+        walls.clear();
+        if !self.nodes.is_empty() {
+            self.visit(0, min, max, walls);
+        }
+        walls.sort_unstable();
+    }
+
+    fn visit(&self, node: usize, min: &V3, max: &V3, walls: &mut Vec::<usize>) {
+        let n = &self.nodes[node];
+        if !boxes_overlap(min, max, &n.min, &n.max) {
+            return;
+        }
+
+        match n.children {
+            Some((left, right)) => {
+                self.visit(left, min, max, walls);
+                self.visit(right, min, max, walls);
+            },
+            None => {
+                for &w in &self.order[n.start..n.end] {
+                    if boxes_overlap(min, max, &self.bounds[w].0, &self.bounds[w].1) {
+                        walls.push(w);
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+fn boxes_overlap(a_min: &V3, a_max: &V3, b_min: &V3, b_max: &V3) -> bool {
+    a_min.x <= b_max.x && b_min.x <= a_max.x
+        && a_min.y <= b_max.y && b_min.y <= a_max.y
+        && a_min.z <= b_max.z && b_min.z <= a_max.z
+}
@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use nalgebra as na;
+use nalgebra::vector;
+
+use super::V3;
+use super::material::{MaterialId, WALL_MATERIAL};
+
+// Static colliders, shared by both backends. Stored as columns like the data driven spheres
+
+
+/// Within rounding of unit length, so a stored normal is kept as it is
+pub fn is_unit(v: &V3) -> bool {
+    (v.norm_squared() - 1.0).abs() <= 1e-5
+}
+
+
+// Taken by every change to any Walls, so two different wall sets never have the same generation
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn next_generation() -> u64 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+
+// Fields are only changed through the methods, so the generation changes with every edit
+// and the wall hierarchy knows when to build again
+#[derive(Debug)]
+pub struct Walls {
+    positions: Vec::<V3>, // center position of wall
+    sizes: Vec::<V3>, // size is width, depth and height, before rotation
+    rotations: Vec::<na::UnitQuaternion::<f32>>, // rotation around the center
+    materials: Vec::<MaterialId>,
+    generation: u64,
+}
+
+impl Default for Walls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Walls {
+    pub fn new() -> Self {
+        Self {
+            positions: vec![],
+            sizes: vec![],
+            rotations: vec![],
+            materials: vec![],
+            generation: next_generation(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Changes whenever a wall is added or changed
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn positions(&self) -> &[V3] {
+        &self.positions
+    }
+
+    pub fn sizes(&self) -> &[V3] {
+        &self.sizes
+    }
+
+    pub fn rotations(&self) -> &[na::UnitQuaternion::<f32>] {
+        &self.rotations
+    }
+
+    pub fn materials(&self) -> &[MaterialId] {
+        &self.materials
+    }
+
+    pub fn add_wall(&mut self, center: V3, size: V3) {
+        self.add_rotated_wall(center, size, na::UnitQuaternion::identity(), WALL_MATERIAL);
+    }
+
+    pub fn add_rotated_wall(&mut self, center: V3, size: V3, rotation: na::UnitQuaternion::<f32>, material: MaterialId) {
+        self.positions.push(center);
+        self.sizes.push(size);
+        self.rotations.push(rotation);
+        self.materials.push(material);
+        self.generation = next_generation();
+    }
+
+    pub fn set_position(&mut self, w_i: usize, center: V3) {
+        self.positions[w_i] = center;
+        self.generation = next_generation();
+    }
+
+    pub fn set_size(&mut self, w_i: usize, size: V3) {
+        self.sizes[w_i] = size;
+        self.generation = next_generation();
+    }
+
+    pub fn set_rotation(&mut self, w_i: usize, rotation: na::UnitQuaternion::<f32>) {
+        self.rotations[w_i] = rotation;
+        self.generation = next_generation();
+    }
+
+    pub fn set_material(&mut self, w_i: usize, material: MaterialId) {
+        self.materials[w_i] = material;
+        self.generation = next_generation();
+    }
+
+    /// Direction from pos towards the closest point on wall w_i, and the distance to that point.
+    /// When pos is inside the wall the direction is towards the closest face, into the wall, and the distance is negative
+    pub fn contact(&self, w_i: usize, pos: &V3) -> (V3, f32) {
+        let rotation = &self.rotations[w_i];
+        let extent = self.sizes[w_i] / 2.0;
+
+        // work in the wall's local frame, where it is an axis aligned box around origin
+        let local = rotation.inverse_transform_vector(&(pos - self.positions[w_i]));
+
+        let closest = vector![
+            na::clamp(local.x, -extent.x, extent.x),
+            na::clamp(local.y, -extent.y, extent.y),
+            na::clamp(local.z, -extent.z, extent.z)];
+
+        if closest != local {
+            let diff = closest - local;
+            let dist = diff.norm();
+            return (rotation * (diff / dist), dist);
+        }
+
+        // center is inside, use the face with the least penetration
+        let depth = extent - local.abs();
+        let axis = depth.imin();
+
+        let mut normal = vector![0.0, 0.0, 0.0];
+        normal[axis] = -local[axis].signum();
+
+        (rotation * normal, -depth[axis])
+    }
+}
+
+
+#[derive(Debug)]
+pub struct Planes {
+    pub points: Vec::<V3>, // any point on the plane
+    pub normals: Vec::<V3>, // unit normal, front side of the plane
+    pub one_sided: Vec::<bool>, // one sided planes are solid behind, two sided planes collide from both sides
+    pub materials: Vec::<MaterialId>,
+}
+
+impl Default for Planes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Planes {
+    pub fn new() -> Self {
+        Self {
+            points: vec![],
+            normals: vec![],
+            one_sided: vec![],
+            materials: vec![]
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn add_plane(&mut self, point: V3, normal: V3, one_sided: bool, material: MaterialId) {
+        self.points.push(point);
+        self.normals.push(normal.normalize());
+        self.one_sided.push(one_sided);
+        self.materials.push(material);
+    }
+
+    /// Like add_plane, but a normal that is already unit length is kept as it is, so a restored snapshot keeps its bits
+    pub fn restore_plane(&mut self, point: V3, normal: V3, one_sided: bool, material: MaterialId) {
+        let normal = if is_unit(&normal) { normal } else { normal.normalize() };
+        self.points.push(point);
+        self.normals.push(normal);
+        self.one_sided.push(one_sided);
+        self.materials.push(material);
+    }
+}
@@ -1,14 +1,28 @@
-use std::collections::HashMap;
+use std::time::Instant;
 use nalgebra as na;
 use nalgebra::vector;
-use quadtree::{QuadTree, Rect, Point, Query};
-use std::fs;
 
-use std::path::Path;
+use super::{Simulation, Backend, Timings, EntityId, V3, lap};
+use super::snapshot::{Snapshot, SphereSnapshot, WallSnapshot, PlaneSnapshot};
+use super::forces::SimConfig;
+use super::friction::Friction;
+use super::material::{Materials, MaterialId};
+use super::colliders::{Walls, Planes};
+use super::broadphase::{Broadphase, BroadphaseKind, WallIndex, DEFAULT_QUADTREE_SCALE};
 
-pub type EntityId = usize;
-pub type V3 = na::Vector3::<f32>;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
+mod narrowphase;
+
+pub use narrowphase::{Narrowphase, NARROWPHASES};
+use narrowphase::calc_lanes;
+#[cfg(feature = "parallel")]
+use narrowphase::LANES;
+
+// Chunks of LANES pairs handed to each rayon task by the batched narrowphase
+#[cfg(feature = "parallel")]
+const PAR_CHUNKS: usize = 16;
 
 
 // All into regarding the simulation
@@ -17,55 +31,228 @@ pub struct State {
     next_id: EntityId,
     pub spheres: ActiveSpheres,
     pub walls: Walls,
+    pub planes: Planes,
+    pub materials: Materials,
+    pub config: SimConfig,
+    pub narrowphase: Narrowphase,
+    /// Run the passes on the rayon thread pool, on by default. Off runs the single threaded step,
+    /// which gives the same result bit for bit
+    #[cfg(feature = "parallel")]
+    pub parallel: bool,
+    timings: Timings,
+    broadphase: Box<dyn Broadphase>,
+    pairs: Vec::<(usize, usize)>,
+    // buffer for the lanes narrowphase, kept between steps. The parallel step gives each task its own
+    contacts: Vec::<(usize, usize)>,
+    wall_index: WallIndex,
 }
 
 
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl State {
     pub fn new() -> Self {
         Self {
             next_id: 1,
             spheres: ActiveSpheres::new(),
-            walls: Walls::new()
+            walls: Walls::new(),
+            planes: Planes::new(),
+            materials: Materials::new(),
+            config: SimConfig::default(),
+            narrowphase: Narrowphase::default(),
+            #[cfg(feature = "parallel")]
+            parallel: true,
+            timings: Timings::default(),
+            broadphase: BroadphaseKind::QuadTree { scale: DEFAULT_QUADTREE_SCALE }.create(),
+            pairs: vec![],
+            contacts: vec![],
+            wall_index: WallIndex::new(),
+        }
+    }
+
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut state = Self::new();
+        state.materials = snapshot.materials.clone();
+        state.config = snapshot.config.clone();
+
+        for s in &snapshot.spheres {
+            state.spheres.add_entity(NewBall {
+                id: s.id,
+                pos: s.pos,
+                vel: s.vel,
+                orientation: s.orientation,
+                ang_vel: s.ang_vel,
+                radius: s.radius,
+                mass: s.mass,
+                material: s.material
+            });
+        }
+
+        for w in &snapshot.walls {
+            state.add_wall_with_material(w.center, w.size, w.rotation, w.material);
         }
+
+        for p in &snapshot.planes {
+            state.planes.restore_plane(p.point, p.normal, p.one_sided, p.material);
+        }
+
+        state.next_id = snapshot.next_id;
+        state
+    }
+}
+
+
+impl Simulation for State {
+
+    fn backend(&self) -> Backend {
+        Backend::DataDriven
+    }
+
+    fn step(&mut self, dt: f32) {
+        step(self, dt);
+    }
+
+    fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    fn reset_timings(&mut self) {
+        self.timings = Timings::default();
     }
 
+    fn broadphase(&self) -> BroadphaseKind {
+        self.broadphase.kind()
+    }
+
+    fn set_broadphase(&mut self, kind: BroadphaseKind) {
+        self.broadphase = kind.create();
+    }
 
-    pub fn add_ball(&mut self, pos: V3, vel: V3, r: f32, mass: f32) -> EntityId {
+    fn narrowphase(&self) -> Option<Narrowphase> {
+        Some(self.narrowphase)
+    }
+
+    fn set_narrowphase(&mut self, kind: Narrowphase) {
+        self.narrowphase = kind;
+    }
+
+    fn add_ball_with_material(&mut self, pos: V3, vel: V3, ang_vel: V3, r: f32, mass: f32, material: MaterialId) -> EntityId {
         let id = self.next_id;
         self.next_id += 1;
         self.spheres.add_entity(NewBall {
             id,
             pos,
             vel,
+            orientation: na::UnitQuaternion::identity(),
+            ang_vel,
             radius: r,
-            mass
+            mass,
+            material
         });
 
         id
     }
 
-    pub fn add_wall(&mut self, pos: V3, size: V3) {
-        self.walls.add_wall(pos, size);
+    fn walls(&self) -> &Walls {
+        &self.walls
+    }
+
+    fn walls_mut(&mut self) -> &mut Walls {
+        &mut self.walls
+    }
+
+    fn planes(&self) -> &Planes {
+        &self.planes
+    }
+
+    fn planes_mut(&mut self) -> &mut Planes {
+        &mut self.planes
+    }
+
+    fn materials(&self) -> &Materials {
+        &self.materials
+    }
+
+    fn materials_mut(&mut self) -> &mut Materials {
+        &mut self.materials
+    }
+
+    fn config(&self) -> &SimConfig {
+        &self.config
     }
 
-    pub fn sphere_radius(&self) -> &Vec::<f32> {
-        &self.spheres.radius
+    fn config_mut(&mut self) -> &mut SimConfig {
+        &mut self.config
     }
 
-    pub fn sphere_positions(&self) -> &Vec::<V3> {
-        &self.spheres.positions
+    fn sphere_count(&self) -> usize {
+        self.spheres.count()
     }
 
-    pub fn dump(&self, path: &str) {
-        let mut res = "".to_string();
+    fn sphere_ids(&self) -> Vec::<EntityId> {
+        self.spheres.ids.clone()
+    }
+
+    fn sphere_radius(&self) -> Vec::<f32> {
+        self.spheres.radius.clone()
+    }
+
+    fn sphere_positions(&self) -> Vec::<V3> {
+        self.spheres.positions.clone()
+    }
+
+    fn sphere_velocities(&self) -> Vec::<V3> {
+        self.spheres.velocities.clone()
+    }
+
+    fn sphere_masses(&self) -> Vec::<f32> {
+        self.spheres.mass.clone()
+    }
+
+    fn sphere_orientations(&self) -> Vec::<na::UnitQuaternion::<f32>> {
+        self.spheres.orientations.clone()
+    }
 
+    fn sphere_angular_velocities(&self) -> Vec::<V3> {
+        self.spheres.angular_velocities.clone()
+    }
 
+    fn snapshot(&self) -> Snapshot {
         let spheres = &self.spheres;
-        for i in 0..spheres.positions.len() {
-            res += &format!("pos:{:?}, vec: {:?}, mass: {:?}\n",spheres.positions[i], spheres.velocities[i], spheres.mass[i]);
+        let walls = &self.walls;
+
+        Snapshot {
+            next_id: self.next_id,
+            spheres: (0..spheres.count()).map(|i| SphereSnapshot {
+                id: spheres.ids[i],
+                pos: spheres.positions[i],
+                vel: spheres.velocities[i],
+                orientation: spheres.orientations[i],
+                ang_vel: spheres.angular_velocities[i],
+                radius: spheres.radius[i],
+                mass: spheres.mass[i],
+                material: spheres.materials[i],
+            }).collect(),
+            walls: (0..walls.count()).map(|i| WallSnapshot {
+                center: walls.positions()[i],
+                size: walls.sizes()[i],
+                rotation: walls.rotations()[i],
+                material: walls.materials()[i],
+            }).collect(),
+            planes: (0..self.planes.count()).map(|i| PlaneSnapshot {
+                point: self.planes.points[i],
+                normal: self.planes.normals[i],
+                one_sided: self.planes.one_sided[i],
+                material: self.planes.materials[i],
+            }).collect(),
+            materials: self.materials.clone(),
+            config: self.config.clone(),
         }
-        fs::write(path, res);
-
     }
 }
 
@@ -73,213 +260,424 @@ impl State {
 #[derive(Default, Clone, Debug)]
 struct Manifold {
     vel_change : V3,
+    ang_vel_change : V3,
     pos_correction: V3,
+    // contacts that gave an impulse this step. Every impulse is computed from the velocities at the start of the
+    // step, so a sphere pushed into several others at once would get too much, the sum is averaged over these
+    contacts: u32,
 }
 
-fn query_points(state: &mut State, i: usize, query_res: &mut Vec::<i32>, ids: &mut Vec::<usize>) {
-    query_res.clear();
-    ids.clear();
-
-    let pos = &state.spheres.positions;
-    let radius = &state.spheres.radius;
 
-    let r = Rect::from_points(Point { x: (pos[i].x - radius[i]) as i32, y: (pos[i].y - radius[i]) as i32},
-                              Point { x: (pos[i].x + radius[i]) as i32, y: (pos[i].y + radius[i]) as i32});
-
-    // use -1 as omit elemnet since getting it is more work than just not processing, since we use j<=i continue
-    state.spheres.positions2.query(r, -1, query_res);
-
-    for q_id in query_res {
-        ids.push(state.spheres.qt_id_to_index(*q_id))
-    }
+// Changes one colliding pair makes to the manifolds of its two spheres. Computing these does not touch the manifolds,
+// so pairs can be computed in parallel and still be added in pair order, giving the same sums as a single thread
+#[derive(Debug)]
+struct PairImpulse {
+    i: usize,
+    j: usize,
+    vel_i: V3,
+    vel_j: V3,
+    friction: Option<PairFriction>,
+    pos_i: V3,
+    pos_j: V3,
+}
 
-    ids.sort();
+#[derive(Debug)]
+struct PairFriction {
+    vel_i: V3,
+    ang_vel_i: V3,
+    vel_j: V3,
+    ang_vel_j: V3,
 }
 
+impl PairImpulse {
+    fn apply(&self, manifolds: &mut [Manifold]) {
+        let (i, j) = (self.i, self.j);
 
-fn impulse_manifolds_linear(state: &mut State) {
-    let count = state.spheres.count();
+        manifolds[i].vel_change -= self.vel_i;
+        manifolds[j].vel_change += self.vel_j;
+        manifolds[i].contacts += 1;
+        manifolds[j].contacts += 1;
 
-    for i in 0..count {
-        for j in (i + 1)..count {
-            calc(state,i,j);
+        if let Some(f) = &self.friction {
+            manifolds[i].vel_change -= f.vel_i;
+            manifolds[i].ang_vel_change -= f.ang_vel_i;
+            manifolds[j].vel_change += f.vel_j;
+            manifolds[j].ang_vel_change += f.ang_vel_j;
         }
+
+        manifolds[i].pos_correction -= self.pos_i;
+        manifolds[j].pos_correction += self.pos_j;
     }
 }
 
-fn impulse_manifolds_quadtree(state: &mut State) {
+
+// Resolves the candidate pairs from the broadphase, adding their impulses in pair order
+fn narrowphase(state: &mut State, manifolds: &mut [Manifold]) {
 
     // parially from
     //https://gamedevelopment.tutsplus.com/tutorials/how-to-create-a-custom-2d-physics-engine-the-basics-and-impulse-resolution--gamedev-6331
 
-    let count = state.spheres.count();
-
-    let mut query_res = vec![];
-    let mut ids : Vec::<usize> = vec![];
-
-    for i in 0..count {
+    let spheres = &state.spheres;
+    let materials = &state.materials;
+
+    match state.narrowphase {
+        Narrowphase::Scalar => {
+            #[cfg(feature = "parallel")]
+            if state.parallel {
+                let impulses: Vec::<Option<PairImpulse>> = state.pairs.par_iter().map(|&(i, j)| calc(spheres, materials, i, j)).collect();
+                for impulse in impulses.iter().flatten() {
+                    impulse.apply(manifolds);
+                }
+                return;
+            }
 
-        query_points(state, i, &mut query_res, &mut ids);
-        for &j in &ids {
-            if j <= i{
-                continue;
+            for &(i, j) in &state.pairs {
+                if let Some(impulse) = calc(spheres, materials, i, j) {
+                    impulse.apply(manifolds);
+                }
+            }
+        },
+        Narrowphase::Lanes => {
+            #[cfg(feature = "parallel")]
+            if state.parallel {
+                let impulses: Vec::<Vec::<PairImpulse>> = state.pairs.par_chunks(LANES * PAR_CHUNKS).map(|pairs| {
+                    let mut impulses = vec![];
+                    calc_lanes(spheres, materials, pairs, &mut vec![], |impulse| impulses.push(impulse));
+                    impulses
+                }).collect();
+                for impulse in impulses.iter().flatten() {
+                    impulse.apply(manifolds);
+                }
+                return;
             }
 
-            calc(state,i,j);
+            calc_lanes(spheres, materials, &state.pairs, &mut state.contacts, |impulse| impulse.apply(manifolds));
         }
     }
 }
 
-fn calc(state: &mut State, i: usize, j: usize) {
-    let pos = &state.spheres.positions;
-    let vel = &state.spheres.velocities;
-    let radius = &state.spheres.radius;
-    let mass = &state.spheres.mass;
+fn calc(spheres: &ActiveSpheres, materials: &Materials, i: usize, j: usize) -> Option<PairImpulse> {
+    let pos = &spheres.positions;
+    let vel = &spheres.velocities;
+    let radius = &spheres.radius;
+    let mass = &spheres.mass;
+    let ang_vel = &spheres.angular_velocities;
+    let inertia = &spheres.inertia;
 
     let dist = (pos[i]- pos[j]).norm();
 
-    if radius[i] + radius[j] >= dist {
+    // not written as a < test, a NaN distance must not count as a collision
+    let touching = radius[i] + radius[j] >= dist;
+    if !touching {
+        return None;
+    }
 
-        //println!("sum_r, d = {:?}", (radius[i] + radius[j], dist));
-        //println!("p_i, p_j = {:?}", (pos[i], pos[j]));
+    //println!("sum_r, d = {:?}", (radius[i] + radius[j], dist));
+    //println!("p_i, p_j = {:?}", (pos[i], pos[j]));
 
-        //println!("{:?}", (vel[j],i, vel[j].y.is_infinite()));
-        let relative_vel = vel[j] - vel[i];
-        let col_norm : V3 = (pos[j] - pos[i]).normalize();
-        let pen_depth = dist - radius[i] + radius[j];
+    //println!("{:?}", (vel[j],i, vel[j].y.is_infinite()));
+    let relative_vel = vel[j] - vel[i];
+    let col_norm : V3 = (pos[j] - pos[i]).normalize();
+    let pen_depth = dist - radius[i] + radius[j];
 
 
-        let vel_along_norm = relative_vel.dot(&col_norm);
+    let vel_along_norm = relative_vel.dot(&col_norm);
 
-        if vel_along_norm > 0.0 {
-            return;
-        }
+    if vel_along_norm > 0.0 {
+        return None;
+    }
 
-        // can also be so each ball has its own, then use lowest
-        let resitution = 0.8; // elasicity e = rel_vel_after_col / rel_vel_before, so 1 is all energy preserved,
+    let contact = materials.contact(spheres.materials[i], spheres.materials[j]);
 
-        let mut impulse_scalar = -(1.0 + resitution) * vel_along_norm;
-        impulse_scalar /= 1.0/mass[i] + 1.0/mass[j];
+    let mut impulse_scalar = -(1.0 + contact.restitution) * vel_along_norm;
+    impulse_scalar /= 1.0/mass[i] + 1.0/mass[j];
 
-        let impulse : V3 = col_norm * impulse_scalar;
+    let impulse : V3 = col_norm * impulse_scalar;
 
+    // friction, oppose sliding of the contact points. This is what transfers spin between balls
+    let r_i : V3 = col_norm * radius[i];
+    let r_j : V3 = -col_norm * radius[j];
+    let contact_vel = (vel[j] + ang_vel[j].cross(&r_j)) - (vel[i] + ang_vel[i].cross(&r_i));
+    let tangent_vel = contact_vel - contact_vel.dot(&col_norm) * col_norm;
+    let slip = tangent_vel.norm();
 
+    let mut friction = None;
+    if slip > f32::EPSILON {
+        let tangent = tangent_vel / slip;
+        // r is perpendicular to the tangent, so the angular part of the effective mass is r²/I
+        let k = 1.0/mass[i] + 1.0/mass[j] + radius[i] * radius[i] / inertia[i] + radius[j] * radius[j] / inertia[j];
+        let f : V3 = -contact.friction.impulse(slip / k, impulse_scalar) * tangent;
 
-        state.spheres.manifolds[i].vel_change -= 1.0/mass[i] * impulse;
-        state.spheres.manifolds[j].vel_change += 1.0/mass[j] * impulse;
+        friction = Some(PairFriction {
+            vel_i: 1.0/mass[i] * f,
+            ang_vel_i: r_i.cross(&f) / inertia[i],
+            vel_j: 1.0/mass[j] * f,
+            ang_vel_j: r_j.cross(&f) / inertia[j],
+        });
+    }
 
-        let percent = 0.1; // between 0.2 and 0.8 usually
-        let correction : V3 = pen_depth / (1.0/mass[i] + 1.0/mass[j]) * percent * col_norm;
+    let percent = 0.1; // between 0.2 and 0.8 usually
+    let correction : V3 = pen_depth / (1.0/mass[i] + 1.0/mass[j]) * percent * col_norm;
+
+    Some(PairImpulse {
+        i,
+        j,
+        vel_i: 1.0/mass[i] * impulse,
+        vel_j: 1.0/mass[j] * impulse,
+        friction,
+        pos_i: 1.0/mass[i] * correction,
+        pos_j: 1.0/mass[j] * correction,
+    })
+}
 
-        state.spheres.manifolds[i].pos_correction -= 1.0/mass[i] * correction;
-        state.spheres.manifolds[j].pos_correction += 1.0/mass[j] * correction;
 
 
+
+fn impulse_walls(state: &State, manifolds: &mut [Manifold]) {
+
+    let spheres = &state.spheres;
+    let materials = &state.materials;
+    let walls = &state.walls;
+    let wall_index = &state.wall_index;
+
+    #[cfg(feature = "parallel")]
+    if state.parallel {
+        manifolds.par_iter_mut().enumerate().for_each_init(Vec::new, |near_walls, (i, manifold)| {
+            impulse_sphere_walls(spheres, materials, walls, wall_index, i, near_walls, manifold);
+        });
+        return;
+    }
+
+    let mut near_walls = vec![];
+    for (i, manifold) in manifolds.iter_mut().enumerate() {
+        impulse_sphere_walls(spheres, materials, walls, wall_index, i, &mut near_walls, manifold);
     }
 }
 
+fn impulse_sphere_walls(spheres: &ActiveSpheres, materials: &Materials, walls: &Walls, wall_index: &WallIndex, i: usize, near_walls: &mut Vec::<usize>, manifold: &mut Manifold) {
+
+    let pos = &spheres.positions;
+    let vel = &spheres.velocities;
+    let radius = &spheres.radius;
+    let mass = &spheres.mass;
+    let ang_vel = &spheres.angular_velocities;
+    let inertia = &spheres.inertia;
+
+    let r = V3::new(radius[i], radius[i], radius[i]);
+    wall_index.query(&(pos[i] - r), &(pos[i] + r), near_walls);
+
+    for &w_i in near_walls.iter() {
+        let (normal, dist) = walls.contact(w_i, &pos[i]);
+
+        if dist <= radius[i] {
+            // collision
+
 
+            // normal points from the sphere towards the wall
+            let vel_along_norm = vel[i].dot(&normal);
 
+            // a sphere already moving away from the wall would be pulled back into it, adding energy
+            if vel_along_norm > 0.0 {
+                let contact = materials.contact(spheres.materials[i], walls.materials()[w_i]);
 
-fn impulse_walls(state: &mut State) {
+                let mut impulse_scalar = (1.0 + contact.restitution) * vel_along_norm;
+                impulse_scalar /= 1.0/mass[i];
 
-    let count = state.spheres.count();
+                let impulse : V3 = normal * impulse_scalar;
+                manifold.vel_change -= 1.0/mass[i] * impulse;
+                manifold.contacts += 1;
 
-    let pos = &state.spheres.positions;
-    let vel = &state.spheres.velocities;
-    let radius = &state.spheres.radius;
-    let mass = &state.spheres.mass;
+                let (vel_change, ang_vel_change) = friction_static(&contact.friction, vel[i], ang_vel[i], normal * radius[i], normal, mass[i], inertia[i], impulse_scalar);
+                manifold.vel_change += vel_change;
+                manifold.ang_vel_change += ang_vel_change;
+            }
 
+            let percent = 0.1; // between 0.2 and 0.8 usually
 
-    let wall_p = &state.walls.positions;
-    let sizes = &state.walls.sizes;
+            let pen_depth = radius[i] - dist;
+            let correction : V3 = (pen_depth / 1.0/mass[i]) * percent * normal;
 
-    let wall_count = state.walls.count();
+            manifold.pos_correction -= 1.0/mass[i] * correction;
 
-    for i in 0..count {
-        for w_i in 0..wall_count {
-            let n = wall_p[w_i] - pos[i];
-            let extent = sizes[w_i]/ 2.0;
+        }
+    }
+}
 
-            let closest =  na::clamp(n, -extent, extent);
 
-            let closest = vector![
-                na::clamp(n.x, -extent.x, extent.x),
-                na::clamp(n.y, -extent.y, extent.y),
-                na::clamp(n.z, -extent.z, extent.z)];
+// Friction between a sphere and a static collider. r is from the sphere center to the contact point,
+// normal_impulse is the size of the normal impulse at the contact. Returns change in velocity and angular velocity
+#[allow(clippy::too_many_arguments)]
+fn friction_static(friction_model: &Friction, vel: V3, ang_vel: V3, r: V3, normal: V3, mass: f32, inertia: f32, normal_impulse: f32) -> (V3, V3) {
+    let contact_vel = vel + ang_vel.cross(&r);
+    let tangent_vel = contact_vel - contact_vel.dot(&normal) * normal;
+    let slip = tangent_vel.norm();
 
-            // TODO: maybe handle closest==n that means center of sphere is inside the cube
+    if slip <= f32::EPSILON {
+        return (vector![0.0, 0.0, 0.0], vector![0.0, 0.0, 0.0]);
+    }
 
-            let mut normal = (wall_p[w_i] - closest) - pos[i];
-            let dist = normal.norm();
-            normal = normal.normalize();
+    // r is perpendicular to the tangent, so the angular part of the effective mass is r²/I
+    let k = 1.0/mass + r.norm_squared() / inertia;
+    let friction : V3 = -friction_model.impulse(slip / k, normal_impulse) * (tangent_vel / slip);
 
+    (friction / mass, r.cross(&friction) / inertia)
+}
 
-            if dist <= radius[i] {
-                // collision
 
+// Below this approach speed a plane contact does not bounce. Otherwise gravity added each step
+// keeps a ball bouncing on the ground forever, like box2d's velocity threshold
+const RESTING_SPEED: f32 = 1.0;
 
-                let vel_along_norm = vel[i].dot(&normal);
+fn impulse_planes(state: &State, manifolds: &mut [Manifold]) {
 
-                let resitution = 1.0;
+    let spheres = &state.spheres;
+    let materials = &state.materials;
+    let planes = &state.planes;
 
-                let mut impulse_scalar = (1.0 + resitution) * vel_along_norm;
-                impulse_scalar /= 1.0/mass[i];
+    #[cfg(feature = "parallel")]
+    if state.parallel {
+        manifolds.par_iter_mut().enumerate().for_each(|(i, manifold)| {
+            impulse_sphere_planes(spheres, materials, planes, i, manifold);
+        });
+        return;
+    }
 
-                let impulse : V3 = normal * impulse_scalar;
-                state.spheres.manifolds[i].vel_change -= 1.0/mass[i] * impulse;
+    for (i, manifold) in manifolds.iter_mut().enumerate() {
+        impulse_sphere_planes(spheres, materials, planes, i, manifold);
+    }
+}
 
-                let percent = 0.1; // between 0.2 and 0.8 usually
+fn impulse_sphere_planes(spheres: &ActiveSpheres, materials: &Materials, planes: &Planes, i: usize, manifold: &mut Manifold) {
 
-                let pen_depth = radius[i] - dist;
-                let correction : V3 = (pen_depth / 1.0/mass[i]) * percent * normal;
+    let pos = &spheres.positions;
+    let vel = &spheres.velocities;
+    let radius = &spheres.radius;
+    let mass = &spheres.mass;
+    let ang_vel = &spheres.angular_velocities;
+    let inertia = &spheres.inertia;
 
-                state.spheres.manifolds[i].pos_correction -= 1.0/mass[i] * correction;
+    for p_i in 0..planes.count() {
+        let mut normal = planes.normals[p_i];
+        let mut dist = (pos[i] - planes.points[p_i]).dot(&normal);
 
-            }
+        if !planes.one_sided[p_i] && dist < 0.0 {
+            normal = -normal;
+            dist = -dist;
+        }
+
+        if dist >= radius[i] {
+            continue;
+        }
+
+        // normal points from the plane towards the sphere
+        let vel_along_norm = vel[i].dot(&normal);
+
+        if vel_along_norm < 0.0 {
+            let contact = materials.contact(spheres.materials[i], planes.materials[p_i]);
+            let resitution = if -vel_along_norm < RESTING_SPEED { 0.0 } else { contact.restitution };
+
+            manifold.vel_change -= (1.0 + resitution) * vel_along_norm * normal;
+            manifold.contacts += 1;
+
+            let impulse_scalar = -(1.0 + resitution) * vel_along_norm * mass[i];
+            let (vel_change, ang_vel_change) = friction_static(&contact.friction, vel[i], ang_vel[i], -normal * radius[i], normal, mass[i], inertia[i], impulse_scalar);
+            manifold.vel_change += vel_change;
+            manifold.ang_vel_change += ang_vel_change;
         }
+
+        let percent = 0.1; // between 0.2 and 0.8 usually
+        let pen_depth = radius[i] - dist;
+        manifold.pos_correction += pen_depth * percent * normal;
     }
 }
 
 
-pub fn step(state: &mut State, dt: f32) {
+fn apply_forces(state: &mut State, dt: f32) {
+    let spheres = &mut state.spheres;
+    let config = &state.config;
 
-    let count = state.spheres.count();
-    // get acceleration of each ball, calculated from collision
+    let pos = &spheres.positions;
+    let mass = &spheres.mass;
 
+    #[cfg(feature = "parallel")]
+    if state.parallel {
+        spheres.velocities.par_iter_mut().enumerate().for_each(|(i, vel)| {
+            *vel += config.acceleration(&pos[i], vel, mass[i]) * dt;
+        });
+        return;
+    }
+
+    for (i, vel) in spheres.velocities.iter_mut().enumerate() {
+        *vel += config.acceleration(&pos[i], vel, mass[i]) * dt;
+    }
+}
 
-    for i in 0..count {
-        state.spheres.manifolds[i].vel_change.x = 0.0;
-        state.spheres.manifolds[i].vel_change.y = 0.0;
-        state.spheres.manifolds[i].vel_change.z = 0.0;
 
-        state.spheres.manifolds[i].pos_correction.x = 0.0;
-        state.spheres.manifolds[i].pos_correction.y = 0.0;
-        state.spheres.manifolds[i].pos_correction.z = 0.0;
+fn integrate(state: &mut State, manifolds: &[Manifold], dt: f32) {
 
+    let spheres = &mut state.spheres;
+    let pos = &mut spheres.positions;
+    let vel = &mut spheres.velocities;
+    let orientation = &mut spheres.orientations;
+    let ang_vel = &mut spheres.angular_velocities;
+
+    #[cfg(feature = "parallel")]
+    if state.parallel {
+        pos.par_iter_mut().zip(vel.par_iter_mut()).zip(orientation.par_iter_mut()).zip(ang_vel.par_iter_mut()).zip(manifolds.par_iter())
+            .for_each(|((((pos, vel), orientation), ang_vel), manifold)| integrate_sphere(pos, vel, orientation, ang_vel, manifold, dt));
+        return;
     }
 
-    #[cfg(not(feature = "linear"))]
-    impulse_manifolds_quadtree(state);
+    pos.iter_mut().zip(vel.iter_mut()).zip(orientation.iter_mut()).zip(ang_vel.iter_mut()).zip(manifolds.iter())
+        .for_each(|((((pos, vel), orientation), ang_vel), manifold)| integrate_sphere(pos, vel, orientation, ang_vel, manifold, dt));
+}
+
+fn integrate_sphere(pos: &mut V3, vel: &mut V3, orientation: &mut na::UnitQuaternion::<f32>, ang_vel: &mut V3, manifold: &Manifold, dt: f32) {
+    let contacts = manifold.contacts.max(1) as f32;
+    *vel += manifold.vel_change / contacts;
+    *pos += *vel * dt + manifold.pos_correction;
+
+    *ang_vel += manifold.ang_vel_change / contacts;
+    *orientation = na::UnitQuaternion::from_scaled_axis(*ang_vel * dt) * *orientation;
+    orientation.renormalize_fast();
+}
+
 
-    #[cfg(feature = "linear")]
-    impulse_manifolds_linear(state);
+pub fn step(state: &mut State, dt: f32) {
 
-    impulse_walls(state);
+    let mut time = Instant::now();
 
-    //
-    let pos = &mut state.spheres.positions;
-    let vel = &mut state.spheres.velocities;
+    // external forces first, so collisions can cancel them, like gravity for a ball resting on a wall
+    apply_forces(state, dt);
+    state.timings.integration += lap(&mut time);
 
-    for i in 0..count {
+    // get acceleration of each ball, calculated from collision
+    // manifolds are taken out of the spheres while the passes read the spheres
+    let mut manifolds = std::mem::take(&mut state.spheres.manifolds);
 
-        vel[i] += state.spheres.manifolds[i].vel_change;
-        pos[i] += vel[i] * dt + state.spheres.manifolds[i].pos_correction;
+    for manifold in manifolds.iter_mut() {
+        *manifold = Manifold::default();
     }
 
-    // reorder quadtree
-    #[cfg(not(feature = "linear"))]
-    state.spheres.order_tree();
+    state.broadphase.update(&state.spheres.positions, &state.spheres.radius);
+    state.wall_index.update(&state.walls);
+    state.timings.tree_rebuild += lap(&mut time);
+
+    state.broadphase.pairs(&state.spheres.positions, &state.spheres.radius, &mut state.pairs);
+    state.timings.broadphase += lap(&mut time);
+
+    narrowphase(state, &mut manifolds);
+    state.timings.narrowphase += lap(&mut time);
+
+    impulse_walls(state, &mut manifolds);
+
+    impulse_planes(state, &mut manifolds);
+    state.timings.walls += lap(&mut time);
+
+    integrate(state, &manifolds, dt);
+
+    state.spheres.manifolds = manifolds;
+    state.timings.integration += lap(&mut time);
 }
 
 
@@ -287,92 +685,65 @@ pub fn step(state: &mut State, dt: f32) {
 
 #[derive(Debug)]
 pub struct ActiveSpheres {
-    qt_id_to_index : HashMap::<i32, usize>,
-    id_to_qt_id : HashMap::<EntityId, i32>,
-    positions2: QuadTree::<EntityId>,
+    ids: Vec::<EntityId>,
     pub positions: Vec::<V3>,
     velocities: Vec::<V3>,
+    orientations: Vec::<na::UnitQuaternion::<f32>>,
+    angular_velocities: Vec::<V3>,
     radius: Vec::<f32>,
     mass: Vec::<f32>,
+    inertia: Vec::<f32>, // moment of inertia, same around every axis for a solid sphere
+    materials: Vec::<MaterialId>,
     manifolds: Vec::<Manifold>
 }
 
+impl Default for ActiveSpheres {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ActiveSpheres {
 
     pub fn new() -> Self {
-        let mut qt = QuadTree::new(Rect::from_points(Point {x: -128, y: -128}, Point { x: 128, y: 128}));
-
-        qt.set_elements_per_node(6);
         Self {
-            qt_id_to_index : HashMap::new(),
-            id_to_qt_id : HashMap::new(),
+            ids: vec![],
             positions: vec![],
             velocities: vec![],
+            orientations: vec![],
+            angular_velocities: vec![],
             radius: vec![],
             mass: vec![],
+            inertia: vec![],
+            materials: vec![],
             manifolds: vec![],
-            positions2: qt,
         }
     }
 
 
-    pub fn qt_id_to_index(&self, qt_id: i32) -> usize {
-        *self.qt_id_to_index.get(&qt_id).unwrap()
-    }
-
     pub fn count(&self) -> usize {
         self.positions.len()
     }
 
     pub fn add_entity(&mut self, new: NewBall) -> usize {
-
-        let bb = Rect::from_points(Point {x: (new.pos.x - new.radius).floor() as i32, y: (new.pos.y - new.radius).floor() as i32},
-                                   Point {x: (new.pos.x + new.radius).ceil()as i32, y: (new.pos.y + new.radius).ceil() as i32});
-
-
-        let element_id = self.positions2.insert(new.id, bb);
         let index = self.positions.len();
 
-
-        self.qt_id_to_index.insert(element_id, index);
-
+        self.ids.push(new.id);
         self.positions.push(new.pos);
         self.velocities.push(new.vel);
+        self.orientations.push(new.orientation);
+        self.angular_velocities.push(new.ang_vel);
         self.radius.push(new.radius);
         self.mass.push(new.mass);
+        self.inertia.push(0.4 * new.mass * new.radius * new.radius);
+        self.materials.push(new.material);
         self.manifolds.push(Manifold {
             vel_change : vector![0.0, 0.0, 0.0],
-            pos_correction: vector![0.0, 0.0, 0.0]});
+            ang_vel_change : vector![0.0, 0.0, 0.0],
+            pos_correction: vector![0.0, 0.0, 0.0],
+            contacts: 0});
         index
     }
-
-    pub fn order_tree(&mut self) {
-
-        let count = self.positions.len();
-
-        let qt = &mut self.positions2;
-        let qt_ids : Vec<(i32, usize)> = self.qt_id_to_index.iter().map(|(&q_id, &index)| (q_id, index)).collect();
-        for &(qt_id, i) in qt_ids.iter() {
-
-            let pos = self.positions[i];
-            let radius =  self.radius[i];
-            let bb = Rect::from_points(Point {x: (pos.x - radius).floor() as i32, y: (pos.y - radius).floor() as i32},
-                                       Point {x: (pos.x + radius). ceil()as i32, y: (pos.y + radius).ceil() as i32});
-
-            qt.remove(qt_id);
-
-            // insert at new position
-            let new_id = qt.insert(i, bb);
-
-            self.qt_id_to_index.insert(new_id, i);
-
-        }
-
-        self.positions2.cleanup();
-    }
-
-
-
 }
 
 #[derive(Debug)]
@@ -380,33 +751,9 @@ pub struct NewBall {
     pub id: EntityId,
     pub pos: V3,
     pub vel: V3,
+    pub orientation: na::UnitQuaternion::<f32>,
+    pub ang_vel: V3,
     pub radius: f32,
-    pub mass : f32
-}
-
-
-#[derive(Debug)]
-pub struct Walls {
-    pub positions: Vec::<V3>, // center position of wall
-    pub sizes: Vec::<V3>, // size is width, depth and height, with
-        // rotation
-}
-
-impl Walls {
-    pub fn new() -> Self {
-        Self {
-            positions: vec![],
-            sizes: vec![]
-        }
-    }
-
-    pub fn count(&self) -> usize {
-        self.positions.len()
-    }
-
-    pub fn add_wall(&mut self, center: V3, size: V3) {
-        self.positions.push(center);
-        self.sizes.push(size);
-
-    }
+    pub mass : f32,
+    pub material: MaterialId
 }
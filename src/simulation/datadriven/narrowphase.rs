@@ -0,0 +1,289 @@
+use std::fmt;
+use wide::{f32x8, CmpGe, CmpGt, CmpLe};
+
+use super::{ActiveSpheres, PairImpulse, PairFriction, V3};
+use crate::simulation::material::Materials;
+
+// Batched narrowphase. Candidate pairs are handled LANES at a time: sphere values are gathered into one array per
+// x, y and z component, then each pass works on WIDTH lanes at once with the SIMD types from wide, choosing with
+// selects instead of branches. Every operation is done in the same order as the nalgebra ops in calc, so the result
+// is bit for bit the same as the scalar narrowphase
+
+
+pub const LANES: usize = 64;
+
+// lanes in one SIMD value
+const WIDTH: usize = 8;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Narrowphase {
+    /// One pair at a time with nalgebra
+    Scalar,
+    #[default]
+    Lanes,
+}
+
+pub const NARROWPHASES: &[Narrowphase] = &[Narrowphase::Scalar, Narrowphase::Lanes];
+
+impl Narrowphase {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Narrowphase::Scalar => "scalar",
+            Narrowphase::Lanes => "lanes",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        NARROWPHASES.iter().copied().find(|n| n.name() == name)
+    }
+}
+
+impl fmt::Display for Narrowphase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+
+type Lane = [f32x8; LANES / WIDTH];
+
+fn lane(value: f32) -> Lane {
+    [f32x8::splat(value); LANES / WIDTH]
+}
+
+fn set(lanes: &mut Lane, l: usize, value: f32) {
+    lanes[l / WIDTH].as_array_mut()[l % WIDTH] = value;
+}
+
+fn get(lanes: &Lane, l: usize) -> f32 {
+    lanes[l / WIDTH].as_array_ref()[l % WIDTH]
+}
+
+// flips the sign bit, exactly like scalar negation. wide's Neg is 0 - x, which turns -0 into +0
+fn neg(v: f32x8) -> f32x8 {
+    v ^ f32x8::splat(-0.0)
+}
+
+// LANES vectors, an array per component
+struct Lane3 {
+    x: Lane,
+    y: Lane,
+    z: Lane,
+}
+
+impl Lane3 {
+    fn new() -> Self {
+        Lane3 { x: lane(0.0), y: lane(0.0), z: lane(0.0) }
+    }
+
+    fn set(&mut self, l: usize, v: V3) {
+        set(&mut self.x, l, v.x);
+        set(&mut self.y, l, v.y);
+        set(&mut self.z, l, v.z);
+    }
+
+    fn get(&self, l: usize) -> V3 {
+        V3::new(get(&self.x, l), get(&self.y, l), get(&self.z, l))
+    }
+}
+
+
+/// Same impulses as calling calc on each pair, given to add in pair order. Pairs that touch and move towards each other
+/// are found first, so the full impulse is only computed in lanes where it is used
+pub(super) fn calc_lanes(spheres: &ActiveSpheres, materials: &Materials, pairs: &[(usize, usize)], contacts: &mut Vec::<(usize, usize)>, mut add: impl FnMut(PairImpulse)) {
+    contacts.clear();
+    for chunk in pairs.chunks(LANES) {
+        contact_lanes(spheres, chunk, contacts);
+    }
+
+    for chunk in contacts.chunks(LANES) {
+        impulse_lanes(spheres, materials, chunk, &mut add);
+    }
+}
+
+
+// The tests at the start of calc, pushes the pairs that pass them
+fn contact_lanes(spheres: &ActiveSpheres, pairs: &[(usize, usize)], contacts: &mut Vec::<(usize, usize)>) {
+    let mut diff = Lane3::new();
+    let mut relative_vel = Lane3::new();
+    let mut radius_sum = lane(0.0);
+
+    for (l, &(i, j)) in pairs.iter().enumerate() {
+        diff.set(l, spheres.positions[j] - spheres.positions[i]);
+        relative_vel.set(l, spheres.velocities[j] - spheres.velocities[i]);
+        set(&mut radius_sum, l, spheres.radius[i] + spheres.radius[j]);
+    }
+
+    // bit l % WIDTH of active[l / WIDTH] is set when lane l is a contact
+    let mut active = [0; LANES / WIDTH];
+    for c in 0..LANES / WIDTH {
+        let (dx, dy, dz) = (diff.x[c], diff.y[c], diff.z[c]);
+
+        // pos_j - pos_i has the same norm as pos_i - pos_j, negating is exact
+        let dist = (dx * dx + dy * dy + dz * dz).sqrt();
+        let vel_along_norm = relative_vel.x[c] * (dx / dist) + relative_vel.y[c] * (dy / dist) + relative_vel.z[c] * (dz / dist);
+
+        // written so a NaN fails them the same way as in calc
+        let touching = radius_sum[c].cmp_ge(dist);
+        let separating = vel_along_norm.cmp_gt(f32x8::ZERO);
+        active[c] = (touching & !separating).move_mask();
+    }
+
+    for (l, &pair) in pairs.iter().enumerate() {
+        if active[l / WIDTH] & (1 << (l % WIDTH)) != 0 {
+            contacts.push(pair);
+        }
+    }
+}
+
+
+// The rest of calc, for pairs that passed contact_lanes
+fn impulse_lanes(spheres: &ActiveSpheres, materials: &Materials, pairs: &[(usize, usize)], add: &mut impl FnMut(PairImpulse)) {
+    let mut pos_i = Lane3::new();
+    let mut pos_j = Lane3::new();
+    let mut vel_i = Lane3::new();
+    let mut vel_j = Lane3::new();
+    let mut ang_vel_i = Lane3::new();
+    let mut ang_vel_j = Lane3::new();
+    let mut radius_i = lane(0.0);
+    let mut radius_j = lane(0.0);
+    let mut mass_i = lane(1.0);
+    let mut mass_j = lane(1.0);
+    let mut inertia_i = lane(1.0);
+    let mut inertia_j = lane(1.0);
+    let mut restitution = lane(0.0);
+    let mut static_friction = lane(0.0);
+    let mut dynamic_friction = lane(0.0);
+
+    for (l, &(i, j)) in pairs.iter().enumerate() {
+        pos_i.set(l, spheres.positions[i]);
+        pos_j.set(l, spheres.positions[j]);
+        vel_i.set(l, spheres.velocities[i]);
+        vel_j.set(l, spheres.velocities[j]);
+        ang_vel_i.set(l, spheres.angular_velocities[i]);
+        ang_vel_j.set(l, spheres.angular_velocities[j]);
+        set(&mut radius_i, l, spheres.radius[i]);
+        set(&mut radius_j, l, spheres.radius[j]);
+        set(&mut mass_i, l, spheres.mass[i]);
+        set(&mut mass_j, l, spheres.mass[j]);
+        set(&mut inertia_i, l, spheres.inertia[i]);
+        set(&mut inertia_j, l, spheres.inertia[j]);
+
+        let contact = materials.contact(spheres.materials[i], spheres.materials[j]);
+        set(&mut restitution, l, contact.restitution);
+        set(&mut static_friction, l, contact.friction.static_coefficient);
+        set(&mut dynamic_friction, l, contact.friction.dynamic_coefficient);
+    }
+
+    let mut vel_change_i = Lane3::new();
+    let mut vel_change_j = Lane3::new();
+    let mut slip = lane(0.0);
+    let mut friction_vel_i = Lane3::new();
+    let mut friction_ang_vel_i = Lane3::new();
+    let mut friction_vel_j = Lane3::new();
+    let mut friction_ang_vel_j = Lane3::new();
+    let mut pos_correction_i = Lane3::new();
+    let mut pos_correction_j = Lane3::new();
+
+    let one = f32x8::ONE;
+
+    for c in 0..LANES / WIDTH {
+        let (ri, rj) = (radius_i[c], radius_j[c]);
+
+        let dx = pos_j.x[c] - pos_i.x[c];
+        let dy = pos_j.y[c] - pos_i.y[c];
+        let dz = pos_j.z[c] - pos_i.z[c];
+        let dist = (dx * dx + dy * dy + dz * dz).sqrt();
+        let (nx, ny, nz) = (dx / dist, dy / dist, dz / dist);
+        let pen_depth = dist - ri + rj;
+
+        let vel_along_norm = (vel_j.x[c] - vel_i.x[c]) * nx + (vel_j.y[c] - vel_i.y[c]) * ny + (vel_j.z[c] - vel_i.z[c]) * nz;
+
+        let inv_mass_i = one / mass_i[c];
+        let inv_mass_j = one / mass_j[c];
+        let inv_mass_sum = inv_mass_i + inv_mass_j;
+
+        let impulse_scalar = neg(one + restitution[c]) * vel_along_norm / inv_mass_sum;
+        let (ix, iy, iz) = (nx * impulse_scalar, ny * impulse_scalar, nz * impulse_scalar);
+
+        vel_change_i.x[c] = ix * inv_mass_i;
+        vel_change_i.y[c] = iy * inv_mass_i;
+        vel_change_i.z[c] = iz * inv_mass_i;
+        vel_change_j.x[c] = ix * inv_mass_j;
+        vel_change_j.y[c] = iy * inv_mass_j;
+        vel_change_j.z[c] = iz * inv_mass_j;
+
+        // friction, r_i and r_j go from each center to the contact point
+        let (rix, riy, riz) = (nx * ri, ny * ri, nz * ri);
+        let (rjx, rjy, rjz) = (neg(nx) * rj, neg(ny) * rj, neg(nz) * rj);
+
+        let (wix, wiy, wiz) = (ang_vel_i.x[c], ang_vel_i.y[c], ang_vel_i.z[c]);
+        let (wjx, wjy, wjz) = (ang_vel_j.x[c], ang_vel_j.y[c], ang_vel_j.z[c]);
+
+        let cvx = (vel_j.x[c] + (wjy * rjz - wjz * rjy)) - (vel_i.x[c] + (wiy * riz - wiz * riy));
+        let cvy = (vel_j.y[c] + (wjz * rjx - wjx * rjz)) - (vel_i.y[c] + (wiz * rix - wix * riz));
+        let cvz = (vel_j.z[c] + (wjx * rjy - wjy * rjx)) - (vel_i.z[c] + (wix * riy - wiy * rix));
+
+        let cv_along_norm = cvx * nx + cvy * ny + cvz * nz;
+        let (tx, ty, tz) = (cvx - nx * cv_along_norm, cvy - ny * cv_along_norm, cvz - nz * cv_along_norm);
+        slip[c] = (tx * tx + ty * ty + tz * tz).sqrt();
+
+        let k = inv_mass_sum + ri * ri / inertia_i[c] + rj * rj / inertia_j[c];
+        // Friction::impulse with both sides computed and the result picked by a select
+        let stick_impulse = slip[c] / k;
+        let slide_impulse = (dynamic_friction[c] * impulse_scalar).min(stick_impulse);
+        let sticks = stick_impulse.cmp_le(static_friction[c] * impulse_scalar);
+        let friction_impulse = neg(sticks.blend(stick_impulse, slide_impulse));
+        let (fx, fy, fz) = ((tx / slip[c]) * friction_impulse, (ty / slip[c]) * friction_impulse, (tz / slip[c]) * friction_impulse);
+
+        friction_vel_i.x[c] = fx * inv_mass_i;
+        friction_vel_i.y[c] = fy * inv_mass_i;
+        friction_vel_i.z[c] = fz * inv_mass_i;
+        friction_ang_vel_i.x[c] = (riy * fz - riz * fy) / inertia_i[c];
+        friction_ang_vel_i.y[c] = (riz * fx - rix * fz) / inertia_i[c];
+        friction_ang_vel_i.z[c] = (rix * fy - riy * fx) / inertia_i[c];
+
+        friction_vel_j.x[c] = fx * inv_mass_j;
+        friction_vel_j.y[c] = fy * inv_mass_j;
+        friction_vel_j.z[c] = fz * inv_mass_j;
+        friction_ang_vel_j.x[c] = (rjy * fz - rjz * fy) / inertia_j[c];
+        friction_ang_vel_j.y[c] = (rjz * fx - rjx * fz) / inertia_j[c];
+        friction_ang_vel_j.z[c] = (rjx * fy - rjy * fx) / inertia_j[c];
+
+        let percent = f32x8::splat(0.1); // between 0.2 and 0.8 usually
+        let correction = pen_depth / inv_mass_sum * percent;
+        let (cx, cy, cz) = (nx * correction, ny * correction, nz * correction);
+
+        pos_correction_i.x[c] = cx * inv_mass_i;
+        pos_correction_i.y[c] = cy * inv_mass_i;
+        pos_correction_i.z[c] = cz * inv_mass_i;
+        pos_correction_j.x[c] = cx * inv_mass_j;
+        pos_correction_j.y[c] = cy * inv_mass_j;
+        pos_correction_j.z[c] = cz * inv_mass_j;
+    }
+
+    for (l, &(i, j)) in pairs.iter().enumerate() {
+        let friction = if get(&slip, l) > f32::EPSILON {
+            Some(PairFriction {
+                vel_i: friction_vel_i.get(l),
+                ang_vel_i: friction_ang_vel_i.get(l),
+                vel_j: friction_vel_j.get(l),
+                ang_vel_j: friction_ang_vel_j.get(l),
+            })
+        } else {
+            None
+        };
+
+        add(PairImpulse {
+            i,
+            j,
+            vel_i: vel_change_i.get(l),
+            vel_j: vel_change_j.get(l),
+            friction,
+            pos_i: pos_correction_i.get(l),
+            pos_j: pos_correction_j.get(l),
+        });
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use super::{Simulation, V3};
+
+// Conservation checks. Totals of energy and momentum over all spheres, and a monitor that logs them every step
+// and flags steps where the energy went up.
+// Without force fields and with restitution 1 the total energy should never rise, collisions and friction only
+// take energy out. Gravity is included as potential energy, force fields are not, so with those energy can rise.
+
+
+/// Totals over all spheres at one point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diagnostics {
+    /// translational plus rotational
+    pub kinetic_energy: f32,
+    /// in gravity, zero at the origin
+    pub potential_energy: f32,
+    pub linear_momentum: V3,
+    /// around the origin, orbital plus spin
+    pub angular_momentum: V3,
+}
+
+impl Diagnostics {
+
+    pub fn measure<S: Simulation + ?Sized>(state: &S) -> Self {
+        let gravity = state.config().gravity.cast::<f64>();
+        let masses = state.sphere_masses();
+        let radius = state.sphere_radius();
+        let positions = state.sphere_positions();
+        let velocities = state.sphere_velocities();
+        let angular_velocities = state.sphere_angular_velocities();
+
+        // summed in f64, so the totals of large scenes don't drift with the order of the spheres
+        let mut kinetic = 0.0;
+        let mut potential = 0.0;
+        let mut linear = nalgebra::Vector3::<f64>::zeros();
+        let mut angular = nalgebra::Vector3::<f64>::zeros();
+
+        for i in 0..masses.len() {
+            let mass = masses[i] as f64;
+            let r = radius[i] as f64;
+            // solid sphere, same as the backends
+            let inertia = 0.4 * mass * r * r;
+            let pos = positions[i].cast::<f64>();
+            let vel = velocities[i].cast::<f64>();
+            let ang_vel = angular_velocities[i].cast::<f64>();
+
+            kinetic += 0.5 * mass * vel.norm_squared() + 0.5 * inertia * ang_vel.norm_squared();
+            potential -= mass * gravity.dot(&pos);
+            linear += mass * vel;
+            angular += pos.cross(&(mass * vel)) + inertia * ang_vel;
+        }
+
+        Self {
+            kinetic_energy: kinetic as f32,
+            potential_energy: potential as f32,
+            linear_momentum: linear.cast::<f32>(),
+            angular_momentum: angular.cast::<f32>(),
+        }
+    }
+
+    pub fn total_energy(&self) -> f32 {
+        self.kinetic_energy + self.potential_energy
+    }
+}
+
+
+pub const CSV_HEADER: &str = "step,kinetic_energy,potential_energy,total_energy,px,py,pz,lx,ly,lz";
+
+
+/// A step where the total energy rose by more than the tolerance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnergyRise {
+    pub step: usize,
+    pub before: f32,
+    pub after: f32,
+}
+
+
+pub struct EnergyMonitor {
+    // csv, one row per step
+    log: Option<BufWriter<File>>,
+    // relative to the energy before the step, None only measures
+    tolerance: Option<f32>,
+    previous: Option<Diagnostics>,
+    // steps seen so far, step 0 is the state before the first step
+    step: usize,
+    rises: Vec::<EnergyRise>,
+}
+
+impl EnergyMonitor {
+
+    /// Logs to log_path when given. With a tolerance, steps where the total energy rises by more than tolerance times
+    /// the energy before the step are flagged
+    pub fn new(log_path: Option<&str>, tolerance: Option<f32>) -> io::Result<Self> {
+        let log = match log_path {
+            Some(path) => {
+                let mut out = BufWriter::new(File::create(path)?);
+                writeln!(out, "{}", CSV_HEADER)?;
+                Some(out)
+            },
+            None => None
+        };
+
+        Ok(Self { log, tolerance, previous: None, step: 0, rises: vec![] })
+    }
+
+    /// Call once before the first step and once after every step
+    pub fn observe(&mut self, state: &dyn Simulation) -> io::Result<Diagnostics> {
+        let step = self.step;
+        self.step += 1;
+
+        let d = state.diagnostics();
+
+        if let Some(out) = &mut self.log {
+            let (p, l) = (d.linear_momentum, d.angular_momentum);
+            writeln!(out, "{},{},{},{},{},{},{},{},{},{}", step, d.kinetic_energy, d.potential_energy, d.total_energy(), p.x, p.y, p.z, l.x, l.y, l.z)?;
+        }
+
+        if let (Some(tolerance), Some(previous)) = (self.tolerance, self.previous) {
+            let (before, after) = (previous.total_energy(), d.total_energy());
+            // NaN is a rise too, the energy is gone either way
+            if after - before > tolerance * before.abs() || (after.is_nan() && !before.is_nan()) {
+                self.rises.push(EnergyRise { step, before, after });
+            }
+        }
+
+        self.previous = Some(d);
+        Ok(d)
+    }
+
+    /// Flagged steps in order, always empty without a tolerance
+    pub fn rises(&self) -> &[EnergyRise] {
+        &self.rises
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.log {
+            Some(out) => out.flush(),
+            None => Ok(())
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use nalgebra as na;
+use nalgebra::vector;
+
+// External forces applied to every sphere each step, shared by both backends.
+
+type V3 = na::Vector3::<f32>;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    pub gravity: V3,
+    pub fields: Vec::<ForceField>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            gravity: vector![0.0, 0.0, 0.0],
+            fields: vec![]
+        }
+    }
+}
+
+impl SimConfig {
+
+    /// Total acceleration on a sphere from gravity and all force fields
+    pub fn acceleration(&self, pos: &V3, vel: &V3, mass: f32) -> V3 {
+        let mut force = vector![0.0, 0.0, 0.0];
+        for field in &self.fields {
+            force += field.force(pos, vel, mass);
+        }
+
+        self.gravity + force / mass
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ForceField {
+    /// Constant force, independent of position and mass
+    Uniform { force: V3 },
+    /// Pulls towards center with acceleration strength / distance², distance is clamped to min_dist to avoid blowing up near the center
+    Attractor { center: V3, strength: f32, min_dist: f32 },
+    /// Force opposite the velocity, proportional to speed
+    Drag { coefficient: f32 },
+    /// Drags spheres inside the box min..max towards the wind velocity
+    Wind { min: V3, max: V3, velocity: V3, coefficient: f32 },
+}
+
+impl ForceField {
+
+    pub fn force(&self, pos: &V3, vel: &V3, mass: f32) -> V3 {
+        match self {
+            ForceField::Uniform { force } => *force,
+            ForceField::Attractor { center, strength, min_dist } => {
+                let dir = center - pos;
+                let dist = dir.norm();
+                if dist == 0.0 {
+                    return vector![0.0, 0.0, 0.0];
+                }
+
+                let d = dist.max(*min_dist);
+                dir / dist * (strength * mass / (d * d))
+            },
+            ForceField::Drag { coefficient } => -coefficient * vel,
+            ForceField::Wind { min, max, velocity, coefficient } => {
+                let inside = pos.x >= min.x && pos.y >= min.y && pos.z >= min.z
+                    && pos.x <= max.x && pos.y <= max.y && pos.z <= max.z;
+
+                if inside {
+                    *coefficient * (velocity - vel)
+                } else {
+                    vector![0.0, 0.0, 0.0]
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// Coulomb friction, shared by both backends. The coefficients come from the materials in contact.
+// Below the static limit a contact sticks and all sliding is removed,
+// above it the contact slides and the friction impulse is the dynamic coefficient times the normal impulse
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Friction {
+    pub static_coefficient: f32,
+    pub dynamic_coefficient: f32,
+}
+
+impl Friction {
+
+    /// Size of the tangential impulse at a contact. stick_impulse is the impulse that would stop the sliding completely,
+    /// normal_impulse is the size of the normal impulse at the contact
+    pub fn impulse(&self, stick_impulse: f32, normal_impulse: f32) -> f32 {
+        if stick_impulse <= self.static_coefficient * normal_impulse {
+            stick_impulse
+        } else {
+            // never more than needed to stop the sliding, otherwise friction would reverse it
+            (self.dynamic_coefficient * normal_impulse).min(stick_impulse)
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use super::friction::Friction;
+
+// Material table shared by both backends. Spheres, walls and planes reference a material by id,
+// and a contact combines the two materials with the table's combine rules.
+
+pub type MaterialId = usize;
+
+/// Always present, used by spheres and planes that are added without a material
+pub const DEFAULT_MATERIAL: MaterialId = 0;
+
+/// Always present, used by walls that are added without a material.
+/// Walls kept all energy before there were materials, so it has restitution 1
+pub const WALL_MATERIAL: MaterialId = 1;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    pub restitution: f32, // elasicity e = rel_vel_after_col / rel_vel_before, so 1 is all energy preserved
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+    pub density: f32, // mass per volume, used when a ball is added without a mass
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            restitution: 0.8,
+            static_friction: 0.5,
+            dynamic_friction: 0.3,
+            density: 1.0,
+        }
+    }
+}
+
+impl Material {
+    pub fn wall() -> Self {
+        Self {
+            restitution: 1.0,
+            ..Self::default()
+        }
+    }
+
+    pub fn sphere_mass(&self, radius: f32) -> f32 {
+        self.density * 4.0 / 3.0 * std::f32::consts::PI * radius * radius * radius
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombineRule {
+    Min,
+    Max,
+    Average,
+    Multiply,
+}
+
+impl CombineRule {
+    pub fn combine(&self, a: f32, b: f32) -> f32 {
+        match self {
+            CombineRule::Min => a.min(b),
+            CombineRule::Max => a.max(b),
+            CombineRule::Average => (a + b) / 2.0,
+            CombineRule::Multiply => a * b,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CombineRule::Min => "min",
+            CombineRule::Max => "max",
+            CombineRule::Average => "average",
+            CombineRule::Multiply => "multiply",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(CombineRule::Min),
+            "max" => Some(CombineRule::Max),
+            "average" => Some(CombineRule::Average),
+            "multiply" => Some(CombineRule::Multiply),
+            _ => None
+        }
+    }
+}
+
+
+/// Combined material values for a pair in contact
+#[derive(Debug, Clone, Copy)]
+pub struct ContactMaterial {
+    pub restitution: f32,
+    pub friction: Friction,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Materials {
+    pub materials: Vec::<Material>,
+    pub restitution_rule: CombineRule,
+    pub friction_rule: CombineRule,
+}
+
+impl Default for Materials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Materials {
+    pub fn new() -> Self {
+        Self {
+            materials: vec![Material::default(), Material::wall()],
+            // max, so the default materials bounce off walls without losing energy
+            restitution_rule: CombineRule::Max,
+            friction_rule: CombineRule::Average,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn add(&mut self, material: Material) -> MaterialId {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn get(&self, id: MaterialId) -> &Material {
+        &self.materials[id]
+    }
+
+    pub fn contact(&self, a: MaterialId, b: MaterialId) -> ContactMaterial {
+        let a = &self.materials[a];
+        let b = &self.materials[b];
+
+        ContactMaterial {
+            restitution: self.restitution_rule.combine(a.restitution, b.restitution),
+            friction: Friction {
+                static_coefficient: self.friction_rule.combine(a.static_friction, b.static_friction),
+                dynamic_coefficient: self.friction_rule.combine(a.dynamic_friction, b.dynamic_friction),
+            }
+        }
+    }
+}
@@ -1,11 +1,217 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+use nalgebra as na;
+use nalgebra::vector;
 
-#[cfg(not(feature = "oop"))]
-mod datadriven;
-#[cfg(not(feature="oop"))]
-pub use datadriven::*;
+pub mod snapshot;
+pub mod forces;
+pub mod friction;
+pub mod material;
+pub mod colliders;
+pub mod broadphase;
+pub mod trajectory;
+pub mod diagnostics;
 
+pub mod datadriven;
+pub mod oop;
 
-#[cfg(feature = "oop")]
-mod oop;
-#[cfg(feature = "oop")]
-pub use oop::*;
+use snapshot::{Snapshot, SnapshotError, Format};
+use forces::{SimConfig, ForceField};
+use material::{Materials, Material, MaterialId, DEFAULT_MATERIAL, WALL_MATERIAL};
+use colliders::{Walls, Planes};
+use broadphase::BroadphaseKind;
+use datadriven::Narrowphase;
+use diagnostics::Diagnostics;
+
+pub type EntityId = usize;
+pub type V3 = na::Vector3::<f32>;
+
+
+/// Common interface of the backends, so they can be picked at runtime and compared in the same binary
+pub trait Simulation {
+
+    fn backend(&self) -> Backend;
+
+    fn step(&mut self, dt: f32);
+
+    /// Time spent in each phase of step since the state was made or the last reset_timings
+    fn timings(&self) -> Timings;
+
+    fn reset_timings(&mut self);
+
+    fn broadphase(&self) -> BroadphaseKind;
+
+    fn set_broadphase(&mut self, kind: BroadphaseKind);
+
+    /// None for backends with only one narrowphase
+    fn narrowphase(&self) -> Option<Narrowphase> {
+        None
+    }
+
+    /// Ignored by backends with only one narrowphase
+    fn set_narrowphase(&mut self, _kind: Narrowphase) {}
+
+    fn add_ball_with_material(&mut self, pos: V3, vel: V3, ang_vel: V3, r: f32, mass: f32, material: MaterialId) -> EntityId;
+
+    fn add_ball(&mut self, pos: V3, vel: V3, r: f32, mass: f32) -> EntityId {
+        self.add_spinning_ball(pos, vel, vector![0.0, 0.0, 0.0], r, mass)
+    }
+
+    fn add_spinning_ball(&mut self, pos: V3, vel: V3, ang_vel: V3, r: f32, mass: f32) -> EntityId {
+        self.add_ball_with_material(pos, vel, ang_vel, r, mass, DEFAULT_MATERIAL)
+    }
+
+    fn walls(&self) -> &Walls;
+
+    fn walls_mut(&mut self) -> &mut Walls;
+
+    fn add_wall(&mut self, pos: V3, size: V3) {
+        self.walls_mut().add_wall(pos, size);
+    }
+
+    fn add_rotated_wall(&mut self, pos: V3, size: V3, rotation: na::UnitQuaternion::<f32>) {
+        self.walls_mut().add_rotated_wall(pos, size, rotation, WALL_MATERIAL);
+    }
+
+    fn add_wall_with_material(&mut self, pos: V3, size: V3, rotation: na::UnitQuaternion::<f32>, material: MaterialId) {
+        self.walls_mut().add_rotated_wall(pos, size, rotation, material);
+    }
+
+    fn planes(&self) -> &Planes;
+
+    fn planes_mut(&mut self) -> &mut Planes;
+
+    fn add_plane(&mut self, point: V3, normal: V3, one_sided: bool) {
+        self.planes_mut().add_plane(point, normal, one_sided, DEFAULT_MATERIAL);
+    }
+
+    fn add_plane_with_material(&mut self, point: V3, normal: V3, one_sided: bool, material: MaterialId) {
+        self.planes_mut().add_plane(point, normal, one_sided, material);
+    }
+
+    fn materials(&self) -> &Materials;
+
+    fn materials_mut(&mut self) -> &mut Materials;
+
+    fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials_mut().add(material)
+    }
+
+    fn config(&self) -> &SimConfig;
+
+    fn config_mut(&mut self) -> &mut SimConfig;
+
+    fn set_gravity(&mut self, gravity: V3) {
+        self.config_mut().gravity = gravity;
+    }
+
+    fn add_force_field(&mut self, field: ForceField) {
+        self.config_mut().fields.push(field);
+    }
+
+    fn sphere_count(&self) -> usize;
+
+    fn sphere_ids(&self) -> Vec::<EntityId>;
+
+    fn sphere_radius(&self) -> Vec::<f32>;
+
+    fn sphere_positions(&self) -> Vec::<V3>;
+
+    fn sphere_velocities(&self) -> Vec::<V3>;
+
+    fn sphere_masses(&self) -> Vec::<f32>;
+
+    fn sphere_orientations(&self) -> Vec::<na::UnitQuaternion::<f32>>;
+
+    fn sphere_angular_velocities(&self) -> Vec::<V3>;
+
+    /// Total energy and momentum of the spheres, for checking conservation between steps
+    fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::measure(self)
+    }
+
+    fn snapshot(&self) -> Snapshot;
+
+    /// Write snapshot to path, binary if path ends with .bin otherwise text
+    fn dump(&self, path: &str) -> Result<(), SnapshotError> {
+        self.snapshot().write(path, Format::from_path(path))
+    }
+}
+
+
+/// Summed time of each phase of step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    /// bringing the broadphase and the wall hierarchy up to date with the spheres
+    pub tree_rebuild: Duration,
+    /// finding candidate pairs in the broadphase
+    pub broadphase: Duration,
+    pub narrowphase: Duration,
+    /// collisions with walls and planes
+    pub walls: Duration,
+    /// external forces and moving the spheres
+    pub integration: Duration,
+}
+
+impl Timings {
+
+    pub fn total(&self) -> Duration {
+        self.tree_rebuild + self.broadphase + self.narrowphase + self.walls + self.integration
+    }
+}
+
+/// Time since start, and restart it, for timing the phases of step one after another
+pub fn lap(start: &mut Instant) -> Duration {
+    let now = Instant::now();
+    let elapsed = now - *start;
+    *start = now;
+    elapsed
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    DataDriven,
+    Oop,
+}
+
+pub const BACKENDS: &[Backend] = &[Backend::DataDriven, Backend::Oop];
+
+impl Backend {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::DataDriven => "datadriven",
+            Backend::Oop => "oop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        BACKENDS.iter().copied().find(|b| b.name() == name)
+    }
+
+    pub fn new_state(&self) -> Box<dyn Simulation> {
+        match self {
+            Backend::DataDriven => Box::new(datadriven::State::new()),
+            Backend::Oop => Box::new(oop::State::new()),
+        }
+    }
+
+    pub fn from_snapshot(&self, snapshot: &Snapshot) -> Box<dyn Simulation> {
+        match self {
+            Backend::DataDriven => Box::new(datadriven::State::from_snapshot(snapshot)),
+            Backend::Oop => Box::new(oop::State::from_snapshot(snapshot)),
+        }
+    }
+
+    pub fn load(&self, path: &str) -> Result<Box<dyn Simulation>, SnapshotError> {
+        Ok(self.from_snapshot(&Snapshot::read(path)?))
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -1,10 +1,14 @@
-use std::collections::HashMap;
+use std::time::Instant;
 use nalgebra as na;
 use nalgebra::vector;
 
-
-pub type EntityId = usize;
-pub type V3 = na::Vector3::<f32>;
+use super::{Simulation, Backend, Timings, EntityId, V3, lap};
+use super::snapshot::{Snapshot, SphereSnapshot, WallSnapshot, PlaneSnapshot};
+use super::forces::SimConfig;
+use super::friction::Friction;
+use super::material::{Materials, MaterialId};
+use super::colliders::{Walls, Planes};
+use super::broadphase::{Broadphase, BroadphaseKind, WallIndex};
 
 // All into regarding the simulation
 #[derive(Debug)]
@@ -12,52 +16,220 @@ pub struct State {
     next_id: EntityId,
     pub spheres: Vec::<Sphere>,
     pub walls: Walls,
+    pub planes: Planes,
+    pub materials: Materials,
+    pub config: SimConfig,
+    broadphase: Box<dyn Broadphase>,
+    pairs: Vec::<(usize, usize)>,
+    wall_index: WallIndex,
+    timings: Timings,
 }
 
 
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl State {
     pub fn new() -> Self {
         Self {
             next_id: 1,
             spheres: Vec::new(),
-            walls: Walls::new()
+            walls: Walls::new(),
+            planes: Planes::new(),
+            materials: Materials::new(),
+            config: SimConfig::default(),
+            broadphase: BroadphaseKind::AllPairs.create(),
+            pairs: vec![],
+            wall_index: WallIndex::new(),
+            timings: Timings::default(),
+        }
+    }
+
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut state = Self::new();
+        state.materials = snapshot.materials.clone();
+        state.config = snapshot.config.clone();
+
+        for s in &snapshot.spheres {
+            state.spheres.push(Sphere {
+                id: s.id,
+                pos: s.pos,
+                vel: s.vel,
+                orientation: s.orientation,
+                ang_vel: s.ang_vel,
+                r: s.radius,
+                mass: s.mass,
+                inertia: 0.4 * s.mass * s.radius * s.radius,
+                material: s.material,
+            });
         }
+
+        for w in &snapshot.walls {
+            state.add_wall_with_material(w.center, w.size, w.rotation, w.material);
+        }
+
+        for p in &snapshot.planes {
+            state.planes.restore_plane(p.point, p.normal, p.one_sided, p.material);
+        }
+
+        state.next_id = snapshot.next_id;
+        state
     }
+}
 
 
-    pub fn add_ball(&mut self, pos: V3, vel: V3, r: f32, mass: f32) -> EntityId {
+impl Simulation for State {
+
+    fn backend(&self) -> Backend {
+        Backend::Oop
+    }
+
+    fn step(&mut self, dt: f32) {
+        step(self, dt);
+    }
+
+    fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    fn reset_timings(&mut self) {
+        self.timings = Timings::default();
+    }
+
+    fn broadphase(&self) -> BroadphaseKind {
+        self.broadphase.kind()
+    }
+
+    fn set_broadphase(&mut self, kind: BroadphaseKind) {
+        self.broadphase = kind.create();
+    }
+
+    fn add_ball_with_material(&mut self, pos: V3, vel: V3, ang_vel: V3, r: f32, mass: f32, material: MaterialId) -> EntityId {
         let id = self.next_id;
         self.next_id += 1;
         self.spheres.push( Sphere {
             id,
             pos,
             vel,
+            orientation: na::UnitQuaternion::identity(),
+            ang_vel,
             r,
             mass,
+            inertia: 0.4 * mass * r * r,
+            material,
         });
 
         id
     }
 
-    pub fn add_wall(&mut self, pos: V3, size: V3) {
-        self.walls.add_wall(pos, size);
+    fn walls(&self) -> &Walls {
+        &self.walls
+    }
+
+    fn walls_mut(&mut self) -> &mut Walls {
+        &mut self.walls
+    }
+
+    fn planes(&self) -> &Planes {
+        &self.planes
     }
 
+    fn planes_mut(&mut self) -> &mut Planes {
+        &mut self.planes
+    }
+
+    fn materials(&self) -> &Materials {
+        &self.materials
+    }
+
+    fn materials_mut(&mut self) -> &mut Materials {
+        &mut self.materials
+    }
+
+    fn config(&self) -> &SimConfig {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut SimConfig {
+        &mut self.config
+    }
+
+    fn sphere_count(&self) -> usize {
+        self.spheres.len()
+    }
+
+    fn sphere_ids(&self) -> Vec::<EntityId> {
+        self.spheres.iter().map(|s| s.id).collect()
+    }
 
-    pub fn sphere_radius(&self) -> Vec::<f32> {
+    fn sphere_radius(&self) -> Vec::<f32> {
         self.spheres.iter().map(|s| s.r).collect()
     }
 
-    pub fn sphere_positions(&self) -> Vec::<V3> {
+    fn sphere_positions(&self) -> Vec::<V3> {
         self.spheres.iter().map(|s| s.pos).collect()
     }
+
+    fn sphere_velocities(&self) -> Vec::<V3> {
+        self.spheres.iter().map(|s| s.vel).collect()
+    }
+
+    fn sphere_masses(&self) -> Vec::<f32> {
+        self.spheres.iter().map(|s| s.mass).collect()
+    }
+
+    fn sphere_orientations(&self) -> Vec::<na::UnitQuaternion::<f32>> {
+        self.spheres.iter().map(|s| s.orientation).collect()
+    }
+
+    fn sphere_angular_velocities(&self) -> Vec::<V3> {
+        self.spheres.iter().map(|s| s.ang_vel).collect()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            next_id: self.next_id,
+            spheres: self.spheres.iter().map(|s| SphereSnapshot {
+                id: s.id,
+                pos: s.pos,
+                vel: s.vel,
+                orientation: s.orientation,
+                ang_vel: s.ang_vel,
+                radius: s.r,
+                mass: s.mass,
+                material: s.material,
+            }).collect(),
+            walls: (0..self.walls.count()).map(|i| WallSnapshot {
+                center: self.walls.positions()[i],
+                size: self.walls.sizes()[i],
+                rotation: self.walls.rotations()[i],
+                material: self.walls.materials()[i],
+            }).collect(),
+            planes: (0..self.planes.count()).map(|i| PlaneSnapshot {
+                point: self.planes.points[i],
+                normal: self.planes.normals[i],
+                one_sided: self.planes.one_sided[i],
+                material: self.planes.materials[i],
+            }).collect(),
+            materials: self.materials.clone(),
+            config: self.config.clone(),
+        }
+    }
 }
 
 
 #[derive(Default, Clone, Debug)]
 struct Manifold {
     vel_change : V3,
+    ang_vel_change : V3,
     pos_correction: V3,
+    // contacts that gave an impulse this step. Every impulse is computed from the velocities at the start of the
+    // step, so a sphere pushed into several others at once would get too much, the sum is averaged over these
+    contacts: u32,
 }
 
 
@@ -72,47 +244,64 @@ fn impulse_manifolds(state: &State) -> Vec::<Manifold> {
     let mut res : Vec::<Manifold>= vec![Default::default(); count];
 
 
-    for i in 0..count {
-        for j in (i + 1)..count {
+    for &(i, j) in &state.pairs {
+        let si = &state.spheres[i];
+        let sj = &state.spheres[j];
 
-            let si = &state.spheres[i];
-            let sj = &state.spheres[j];
 
+        let dist = (si.pos- sj.pos).norm();
 
-            let dist = (si.pos- sj.pos).norm();
+        if si.r + sj.r >= dist {
 
-            if si.r + sj.r >= dist {
+            let relative_vel = sj.vel - si.vel;
+            let col_norm : V3 = (sj.pos - si.pos).normalize();
+            let pen_depth = dist - si.r + sj.r;
 
-                let relative_vel = sj.vel - si.vel;
-                let col_norm : V3 = (sj.pos - si.pos).normalize();
-                let pen_depth = dist - si.r + sj.r;
+            let vel_along_norm = relative_vel.dot(&col_norm);
 
-                let vel_along_norm = relative_vel.dot(&col_norm);
+            if vel_along_norm > 0.0 {
+                continue;
+            }
 
-                if vel_along_norm > 0.0 {
-                    continue;
-                }
+            let contact = state.materials.contact(si.material, sj.material);
 
-                // can also be so each ball has its own, then use lowest
-                let resitution = 1.0; // elasicity e = rel_vel_after_col / rel_vel_before, so 1 is all energy preserved,
+            let mut impulse_scalar = -(1.0 + contact.restitution) * vel_along_norm;
+            impulse_scalar /= 1.0/si.mass + 1.0/sj.mass;
 
-                let mut impulse_scalar = -(1.0 + resitution) * vel_along_norm;
-                impulse_scalar /= 1.0/si.mass + 1.0/sj.mass;
+            let impulse : V3 = col_norm * impulse_scalar;
 
-                let impulse : V3 = col_norm * impulse_scalar;
 
+            res[i].vel_change -= 1.0/si.mass * impulse;
+            res[j].vel_change += 1.0/sj.mass * impulse;
+            res[i].contacts += 1;
+            res[j].contacts += 1;
 
-                res[i].vel_change -= 1.0/si.mass * impulse;
-                res[j].vel_change += 1.0/sj.mass * impulse;
+            // friction, oppose sliding of the contact points. This is what transfers spin between balls
+            let r_i : V3 = col_norm * si.r;
+            let r_j : V3 = -col_norm * sj.r;
+            let contact_vel = sj.contact_velocity(&r_j) - si.contact_velocity(&r_i);
+            let tangent_vel = contact_vel - contact_vel.dot(&col_norm) * col_norm;
+            let slip = tangent_vel.norm();
 
-                let percent = 0.1; // between 0.2 and 0.8 usually
-                let correction : V3 = pen_depth / (1.0/si.mass + 1.0/sj.mass) * percent * col_norm;
-                res[i].pos_correction -= 1.0/si.mass * correction;
-                res[j].pos_correction += 1.0/sj.mass * correction;
+            if slip > f32::EPSILON {
+                let tangent = tangent_vel / slip;
+                // r is perpendicular to the tangent, so the angular part of the effective mass is r²/I
+                let k = 1.0/si.mass + 1.0/sj.mass + si.r * si.r / si.inertia + sj.r * sj.r / sj.inertia;
+                let friction : V3 = -contact.friction.impulse(slip / k, impulse_scalar) * tangent;
 
-                //println!("{:?}", impulse);
-                //println!("res ={:.2?}", res);
+                res[i].vel_change -= 1.0/si.mass * friction;
+                res[i].ang_vel_change -= r_i.cross(&friction) / si.inertia;
+                res[j].vel_change += 1.0/sj.mass * friction;
+                res[j].ang_vel_change += r_j.cross(&friction) / sj.inertia;
             }
+
+            let percent = 0.1; // between 0.2 and 0.8 usually
+            let correction : V3 = pen_depth / (1.0/si.mass + 1.0/sj.mass) * percent * col_norm;
+            res[i].pos_correction -= 1.0/si.mass * correction;
+            res[j].pos_correction += 1.0/sj.mass * correction;
+
+            //println!("{:?}", impulse);
+            //println!("res ={:.2?}", res);
         }
     }
 
@@ -121,77 +310,143 @@ fn impulse_manifolds(state: &State) -> Vec::<Manifold> {
 
 
 
-fn impulse_walls(state: &mut State, manifolds: &mut Vec::<Manifold>) {
+fn impulse_walls(state: &State, manifolds: &mut [Manifold]) {
 
-    let count = state.spheres.len();
+    let mut near_walls = vec![];
 
-    let wall_p = &state.walls.positions;
-    let sizes = &state.walls.sizes;
+    for (si, manifold) in state.spheres.iter().zip(manifolds.iter_mut()) {
+        let r = V3::new(si.r, si.r, si.r);
+        state.wall_index.query(&(si.pos - r), &(si.pos + r), &mut near_walls);
 
-    let wall_count = state.walls.count();
+        for &w_i in &near_walls {
 
-    for i in 0..count {
-        for w_i in 0..wall_count {
+            let (normal, dist) = state.walls.contact(w_i, &si.pos);
 
-            let si = &state.spheres[i];
+            if dist <= si.r {
+                // collision
 
-            let n = wall_p[w_i] - si.pos;
-            let extent = sizes[w_i]/ 2.0;
 
-            let closest =  na::clamp(n, -extent, extent);
+                // normal points from the sphere towards the wall
+                let vel_along_norm = si.vel.dot(&normal);
 
-            let closest = vector![
-                na::clamp(n.x, -extent.x, extent.x),
-                na::clamp(n.y, -extent.y, extent.y),
-                na::clamp(n.z, -extent.z, extent.z)];
+                // a sphere already moving away from the wall would be pulled back into it, adding energy
+                if vel_along_norm > 0.0 {
+                    let contact = state.materials.contact(si.material, state.walls.materials()[w_i]);
 
-            // TODO: maybe handle closest==n that means center of sphere is inside the cube
+                    let mut impulse_scalar = (1.0 + contact.restitution) * vel_along_norm;
+                    impulse_scalar /= 1.0/si.mass;
 
-            let mut normal = (wall_p[w_i] - closest) - si.pos;
-            let dist = normal.norm();
-            normal = normal.normalize();
+                    let impulse : V3 = normal * impulse_scalar;
+                    manifold.vel_change -= 1.0/si.mass * impulse;
+                    manifold.contacts += 1;
 
+                    let (vel_change, ang_vel_change) = si.friction_static(&contact.friction, &(normal * si.r), &normal, impulse_scalar);
+                    manifold.vel_change += vel_change;
+                    manifold.ang_vel_change += ang_vel_change;
+                }
 
-            if dist <= si.r {
-                // collision
 
+                let percent = 0.1; // between 0.2 and 0.8 usually
 
-                let vel_along_norm = si.vel.dot(&normal);
+                let pen_depth = si.r - dist;
+                let correction : V3 = (pen_depth / 1.0/si.mass) * percent * normal;
 
-                let resitution = 1.0;
+                manifold.pos_correction -= 1.0/si.mass * correction;
+            }
+        }
+    }
+}
 
-                let mut impulse_scalar = (1.0 + resitution) * vel_along_norm;
-                impulse_scalar /= 1.0/si.mass;
 
-                let impulse : V3 = normal * impulse_scalar;
-                manifolds[i].vel_change -= 1.0/si.mass * impulse;
+// Below this approach speed a plane contact does not bounce. Otherwise gravity added each step
+// keeps a ball bouncing on the ground forever, like box2d's velocity threshold
+const RESTING_SPEED: f32 = 1.0;
 
+fn impulse_planes(state: &State, manifolds: &mut [Manifold]) {
 
-                let percent = 0.1; // between 0.2 and 0.8 usually
+    let planes = &state.planes;
 
-                let pen_depth = si.r - dist;
-                let correction : V3 = (pen_depth / 1.0/si.mass) * percent * normal;
+    for (i, si) in state.spheres.iter().enumerate() {
+        for p_i in 0..planes.count() {
+            let mut normal = planes.normals[p_i];
+            let mut dist = (si.pos - planes.points[p_i]).dot(&normal);
+
+            if !planes.one_sided[p_i] && dist < 0.0 {
+                normal = -normal;
+                dist = -dist;
+            }
 
-                manifolds[i].pos_correction -= 1.0/si.mass * correction;
+            if dist >= si.r {
+                continue;
             }
+
+            // normal points from the plane towards the sphere
+            let vel_along_norm = si.vel.dot(&normal);
+
+            if vel_along_norm < 0.0 {
+                let contact = state.materials.contact(si.material, planes.materials[p_i]);
+                let resitution = if -vel_along_norm < RESTING_SPEED { 0.0 } else { contact.restitution };
+
+                manifolds[i].vel_change -= (1.0 + resitution) * vel_along_norm * normal;
+                manifolds[i].contacts += 1;
+
+                let impulse_scalar = -(1.0 + resitution) * vel_along_norm * si.mass;
+                let (vel_change, ang_vel_change) = si.friction_static(&contact.friction, &(-normal * si.r), &normal, impulse_scalar);
+                manifolds[i].vel_change += vel_change;
+                manifolds[i].ang_vel_change += ang_vel_change;
+            }
+
+            let percent = 0.1; // between 0.2 and 0.8 usually
+            let pen_depth = si.r - dist;
+            manifolds[i].pos_correction += pen_depth * percent * normal;
         }
     }
 }
 
 
+fn apply_forces(state: &mut State, dt: f32) {
+    for s in &mut state.spheres {
+        s.vel += state.config.acceleration(&s.pos, &s.vel, s.mass) * dt;
+    }
+}
+
+
 pub fn step(state: &mut State, dt: f32) {
 
-    let count = state.spheres.len();
+    let mut time = Instant::now();
+
+    // external forces first, so collisions can cancel them, like gravity for a ball resting on a wall
+    apply_forces(state, dt);
+    state.timings.integration += lap(&mut time);
+
     // get acceleration of each ball, calculated from collision
+    let positions = state.sphere_positions();
+    let radius = state.sphere_radius();
+    state.broadphase.update(&positions, &radius);
+    state.wall_index.update(&state.walls);
+    state.timings.tree_rebuild += lap(&mut time);
+
+    state.broadphase.pairs(&positions, &radius, &mut state.pairs);
+    state.timings.broadphase += lap(&mut time);
+
     let mut manifolds = impulse_manifolds(state);
+    state.timings.narrowphase += lap(&mut time);
 
     impulse_walls(state, &mut manifolds);
 
-    for i in 0..count {
-        let s = &mut state.spheres[i];
-        s.vel += manifolds[i].vel_change;
-        s.pos += s.vel * dt + manifolds[i].pos_correction;
+    impulse_planes(state, &mut manifolds);
+    state.timings.walls += lap(&mut time);
+
+    for (s, manifold) in state.spheres.iter_mut().zip(&manifolds) {
+        let contacts = manifold.contacts.max(1) as f32;
+        s.vel += manifold.vel_change / contacts;
+        s.pos += s.vel * dt + manifold.pos_correction;
+
+        s.ang_vel += manifold.ang_vel_change / contacts;
+        s.orientation = na::UnitQuaternion::from_scaled_axis(s.ang_vel * dt) * s.orientation;
+        s.orientation.renormalize_fast();
     }
+    state.timings.integration += lap(&mut time);
 }
 
 
@@ -201,42 +456,45 @@ pub struct Sphere {
     pub id: usize,
     pub pos: V3,
     pub vel: V3,
+    pub orientation: na::UnitQuaternion::<f32>,
+    pub ang_vel: V3,
     pub r: f32,
     pub mass: f32,
+    pub inertia: f32, // moment of inertia, same around every axis for a solid sphere
+    pub material: MaterialId,
 }
 
-#[derive(Debug)]
-pub struct NewBall {
-    pub id: EntityId,
-    pub pos: V3,
-    pub vel: V3,
-    pub radius: f32,
-    pub mass : f32
-}
+impl Sphere {
 
+    /// Velocity of the point on the surface at r from the center
+    pub fn contact_velocity(&self, r: &V3) -> V3 {
+        self.vel + self.ang_vel.cross(r)
+    }
 
-#[derive(Debug)]
-pub struct Walls {
-    pub positions: Vec::<V3>, // center position of wall
-    pub sizes: Vec::<V3>, // size is width, depth and height, with
-        // rotation
-}
+    /// Friction against a static collider. r is from the center to the contact point, normal_impulse is the size of the normal impulse
+    /// at the contact. Returns change in velocity and angular velocity
+    fn friction_static(&self, friction_model: &Friction, r: &V3, normal: &V3, normal_impulse: f32) -> (V3, V3) {
+        let contact_vel = self.contact_velocity(r);
+        let tangent_vel = contact_vel - contact_vel.dot(normal) * normal;
+        let slip = tangent_vel.norm();
 
-impl Walls {
-    pub fn new() -> Self {
-        Self {
-            positions: vec![],
-            sizes: vec![]
+        if slip <= f32::EPSILON {
+            return (vector![0.0, 0.0, 0.0], vector![0.0, 0.0, 0.0]);
         }
-    }
 
-    pub fn count(&self) -> usize {
-        self.positions.len()
-    }
-
-    pub fn add_wall(&mut self, center: V3, size: V3) {
-        self.positions.push(center);
-        self.sizes.push(size);
+        // r is perpendicular to the tangent, so the angular part of the effective mass is r²/I
+        let k = 1.0/self.mass + r.norm_squared() / self.inertia;
+        let friction : V3 = -friction_model.impulse(slip / k, normal_impulse) * (tangent_vel / slip);
 
+        (friction / self.mass, r.cross(&friction) / self.inertia)
     }
 }
+
+#[derive(Debug)]
+pub struct NewBall {
+    pub id: EntityId,
+    pub pos: V3,
+    pub vel: V3,
+    pub radius: f32,
+    pub mass : f32
+}
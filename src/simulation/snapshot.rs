@@ -0,0 +1,679 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use nalgebra as na;
+use nalgebra::vector;
+
+use super::material::{Materials, Material, MaterialId, CombineRule, DEFAULT_MATERIAL, WALL_MATERIAL};
+use super::colliders::is_unit;
+use super::forces::{SimConfig, ForceField};
+
+// Versioned snapshot of a simulation state, shared by both backends.
+// Can be written as text, readable and diffable, or as compact little endian binary.
+// Floats are written with rust's shortest round trip formatting, so both formats restore the state exactly.
+
+// Older versions can still be read
+// 1: initial
+// 2: planes
+// 3: wall rotation
+// 4: sphere orientation and angular velocity
+// 5: materials
+// 6: gravity and force fields
+pub const VERSION: u32 = 6;
+
+const TEXT_HEADER: &str = "snapshot";
+const BINARY_MAGIC: &[u8; 6] = b"DDSNAP";
+
+type V3 = na::Vector3::<f32>;
+type Rotation = na::UnitQuaternion::<f32>;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub next_id: usize,
+    pub spheres: Vec::<SphereSnapshot>,
+    pub walls: Vec::<WallSnapshot>,
+    pub planes: Vec::<PlaneSnapshot>,
+    pub materials: Materials,
+    pub config: SimConfig,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SphereSnapshot {
+    pub id: usize,
+    pub pos: V3,
+    pub vel: V3,
+    pub orientation: Rotation,
+    pub ang_vel: V3,
+    pub radius: f32,
+    pub mass: f32,
+    pub material: MaterialId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WallSnapshot {
+    pub center: V3,
+    pub size: V3,
+    pub rotation: Rotation,
+    pub material: MaterialId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaneSnapshot {
+    pub point: V3,
+    pub normal: V3,
+    pub one_sided: bool,
+    pub material: MaterialId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Binary
+}
+
+impl Format {
+    // .bin is binary, everything else is text
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension() {
+            Some(ext) if ext == "bin" => Format::Binary,
+            _ => Format::Text
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    UnsupportedVersion(u32),
+    Parse { line: usize, msg: String },
+    Binary(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "{}", err),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}, expected 1 to {}", v, VERSION),
+            SnapshotError::Parse { line: 0, msg } => write!(f, "{}", msg),
+            SnapshotError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            SnapshotError::Binary(msg) => write!(f, "invalid binary snapshot: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(err: std::io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+
+impl Snapshot {
+
+    pub fn write(&self, path: &str, format: Format) -> Result<(), SnapshotError> {
+        match format {
+            Format::Text => fs::write(path, self.to_text())?,
+            Format::Binary => fs::write(path, self.to_bytes())?,
+        }
+        Ok(())
+    }
+
+    // Format is detected from the content, not the file name
+    pub fn read(path: &str) -> Result<Self, SnapshotError> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(BINARY_MAGIC) {
+            Self::from_bytes(&bytes)
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| SnapshotError::Parse { line: 1, msg: "not valid utf-8".to_string() })?;
+            Self::from_text(&text)
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut res = format!("{} {}\n", TEXT_HEADER, VERSION);
+        res += &format!("next_id {}\n", self.next_id);
+
+        res += &format!("gravity value={}\n", fmt_v3(&self.config.gravity));
+        for field in &self.config.fields {
+            res += &match field {
+                ForceField::Uniform { force } => format!("uniform force={}\n", fmt_v3(force)),
+                ForceField::Attractor { center, strength, min_dist } => format!("attractor center={} strength={} min_dist={}\n", fmt_v3(center), strength, min_dist),
+                ForceField::Drag { coefficient } => format!("drag coefficient={}\n", coefficient),
+                ForceField::Wind { min, max, velocity, coefficient } => format!("wind min={} max={} velocity={} coefficient={}\n", fmt_v3(min), fmt_v3(max), fmt_v3(velocity), coefficient),
+            };
+        }
+
+        res += &format!("rules restitution={} friction={}\n", self.materials.restitution_rule.name(), self.materials.friction_rule.name());
+        for m in &self.materials.materials {
+            res += &format!("material restitution={} static_friction={} dynamic_friction={} density={}\n", m.restitution, m.static_friction, m.dynamic_friction, m.density);
+        }
+
+        for s in &self.spheres {
+            res += &format!("sphere id={} pos={} vel={} orientation={} ang_vel={} radius={} mass={} material={}\n",
+                            s.id, fmt_v3(&s.pos), fmt_v3(&s.vel), fmt_rotation(&s.orientation), fmt_v3(&s.ang_vel), s.radius, s.mass, s.material);
+        }
+
+        for w in &self.walls {
+            res += &format!("wall center={} size={} rotation={} material={}\n", fmt_v3(&w.center), fmt_v3(&w.size), fmt_rotation(&w.rotation), w.material);
+        }
+
+        for p in &self.planes {
+            res += &format!("plane point={} normal={} one_sided={} material={}\n", fmt_v3(&p.point), fmt_v3(&p.normal), p.one_sided, p.material);
+        }
+
+        res
+    }
+
+    pub fn from_text(src: &str) -> Result<Self, SnapshotError> {
+        let mut lines = src.lines().enumerate().map(|(i, l)| (i + 1, l.trim())).filter(|(_, l)| !l.is_empty());
+
+        let err = |line: usize, msg: String| SnapshotError::Parse { line, msg };
+
+        let (line, header) = lines.next().ok_or_else(|| err(1, "empty snapshot".to_string()))?;
+        let version = match header.split_once(' ') {
+            Some((TEXT_HEADER, v)) => v.trim().parse::<u32>().map_err(|_| err(line, format!("invalid version '{}'", v)))?,
+            _ => return Err(err(line, format!("expected '{} <version>'", TEXT_HEADER)))
+        };
+
+        if version == 0 || version > VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let mut next_id = None;
+        let mut spheres = vec![];
+        let mut walls = vec![];
+        let mut planes = vec![];
+        // before version 6 there is no gravity and there are no force fields
+        let mut config = SimConfig::default();
+        // before version 5 there is only the default materials
+        let mut materials = Materials::new();
+        if version >= 5 {
+            materials.materials.clear();
+        }
+
+        for (line, text) in lines {
+            let mut tokens = text.split_whitespace();
+            // Empty lines are filtered, so there is always a first token
+            let kind = tokens.next().unwrap();
+
+            if kind == "next_id" {
+                let v = tokens.next().ok_or_else(|| err(line, "missing value for next_id".to_string()))?;
+                next_id = Some(v.parse::<usize>().map_err(|_| err(line, format!("invalid next_id '{}'", v)))?);
+                if let Some(t) = tokens.next() {
+                    return Err(err(line, format!("unexpected '{}' after next_id", t)));
+                }
+                continue;
+            }
+
+            let mut values : Vec::<(&str, &str)> = vec![];
+            for t in tokens {
+                let (key, value) = t.split_once('=').ok_or_else(|| err(line, format!("expected key=value, found '{}'", t)))?;
+                if values.iter().any(|(k, _)| *k == key) {
+                    return Err(err(line, format!("'{}' is given more than once", key)));
+                }
+                values.push((key, value));
+            }
+
+            // keys read by the entry, anything else in values is unknown
+            let read = RefCell::new(Vec::<String>::new());
+            let get = |key: &str| {
+                read.borrow_mut().push(key.to_string());
+                values.iter().find(|(k, _)| *k == key).map(|(_, v)| *v).ok_or_else(|| err(line, format!("missing '{}'", key)))
+            };
+            let f32_ = |key: &str| get(key).and_then(|v| v.parse::<f32>().map_err(|_| err(line, format!("'{}' expects a number, found '{}'", key, v))));
+            let v3 = |key: &str| get(key).and_then(|v| parse_v3(v).ok_or_else(|| err(line, format!("'{}' expects x,y,z, found '{}'", key, v))));
+            let rotation = |key: &str| get(key).and_then(|v| parse_rotation(v).ok_or_else(|| err(line, format!("'{}' expects i,j,k,w, found '{}'", key, v))));
+            let material = |default: MaterialId| if version >= 5 {
+                get("material").and_then(|v| v.parse::<MaterialId>().map_err(|_| err(line, format!("invalid material '{}'", v))))
+            } else {
+                Ok(default)
+            };
+            let rule = |key: &str| get(key).and_then(|v| CombineRule::from_name(v).ok_or_else(|| err(line, format!("unknown combine rule '{}'", v))));
+
+            match kind {
+                "sphere" => {
+                    let id = get("id").and_then(|v| v.parse::<usize>().map_err(|_| err(line, format!("invalid id '{}'", v))))?;
+                    let (orientation, ang_vel) = if version >= 4 {
+                        (rotation("orientation")?, v3("ang_vel")?)
+                    } else {
+                        (Rotation::identity(), vector![0.0, 0.0, 0.0])
+                    };
+
+                    spheres.push(SphereSnapshot {
+                        id,
+                        pos: v3("pos")?,
+                        vel: v3("vel")?,
+                        orientation,
+                        ang_vel,
+                        radius: f32_("radius")?,
+                        mass: f32_("mass")?,
+                        material: material(DEFAULT_MATERIAL)?,
+                    });
+                },
+                "wall" => {
+                    let rotation = if version >= 3 {
+                        rotation("rotation")?
+                    } else {
+                        Rotation::identity()
+                    };
+
+                    walls.push(WallSnapshot {
+                        center: v3("center")?,
+                        size: v3("size")?,
+                        rotation,
+                        material: material(WALL_MATERIAL)?,
+                    });
+                },
+                "plane" if version >= 2 => {
+                    let one_sided = get("one_sided").and_then(|v| v.parse::<bool>().map_err(|_| err(line, format!("'one_sided' expects true or false, found '{}'", v))))?;
+                    planes.push(PlaneSnapshot {
+                        point: v3("point")?,
+                        normal: v3("normal")?,
+                        one_sided,
+                        material: material(DEFAULT_MATERIAL)?,
+                    });
+                },
+                "rules" if version >= 5 => {
+                    materials.restitution_rule = rule("restitution")?;
+                    materials.friction_rule = rule("friction")?;
+                },
+                "material" if version >= 5 => {
+                    materials.materials.push(Material {
+                        restitution: f32_("restitution")?,
+                        static_friction: f32_("static_friction")?,
+                        dynamic_friction: f32_("dynamic_friction")?,
+                        density: f32_("density")?,
+                    });
+                },
+                "gravity" if version >= 6 => {
+                    config.gravity = v3("value")?;
+                },
+                "uniform" if version >= 6 => {
+                    config.fields.push(ForceField::Uniform { force: v3("force")? });
+                },
+                "attractor" if version >= 6 => {
+                    config.fields.push(ForceField::Attractor { center: v3("center")?, strength: f32_("strength")?, min_dist: f32_("min_dist")? });
+                },
+                "drag" if version >= 6 => {
+                    config.fields.push(ForceField::Drag { coefficient: f32_("coefficient")? });
+                },
+                "wind" if version >= 6 => {
+                    config.fields.push(ForceField::Wind { min: v3("min")?, max: v3("max")?, velocity: v3("velocity")?, coefficient: f32_("coefficient")? });
+                },
+                _ => return Err(err(line, format!("unknown entry '{}'", kind)))
+            }
+
+            if let Some((key, _)) = values.iter().find(|(k, _)| !read.borrow().iter().any(|r| r == k)) {
+                return Err(err(line, format!("unknown key '{}'", key)));
+            }
+        }
+
+        let next_id = next_id.ok_or_else(|| err(0, "missing next_id".to_string()))?;
+
+        let snapshot = Self { next_id, spheres, walls, planes, materials, config };
+        snapshot.check().map_err(|msg| err(0, msg))?;
+        Ok(snapshot)
+    }
+
+    pub fn to_bytes(&self) -> Vec::<u8> {
+        let mut res = Vec::with_capacity(BINARY_MAGIC.len() + 52 + self.config.fields.len() * 41 + self.spheres.len() * 80 + self.walls.len() * 40 + self.planes.len() * 33 + self.materials.count() * 16);
+
+        res.extend_from_slice(BINARY_MAGIC);
+        res.extend_from_slice(&VERSION.to_le_bytes());
+        res.extend_from_slice(&(self.next_id as u64).to_le_bytes());
+
+        push_v3(&mut res, &self.config.gravity);
+        res.extend_from_slice(&(self.config.fields.len() as u64).to_le_bytes());
+        for field in &self.config.fields {
+            match field {
+                ForceField::Uniform { force } => {
+                    res.push(0);
+                    push_v3(&mut res, force);
+                },
+                ForceField::Attractor { center, strength, min_dist } => {
+                    res.push(1);
+                    push_v3(&mut res, center);
+                    res.extend_from_slice(&strength.to_le_bytes());
+                    res.extend_from_slice(&min_dist.to_le_bytes());
+                },
+                ForceField::Drag { coefficient } => {
+                    res.push(2);
+                    res.extend_from_slice(&coefficient.to_le_bytes());
+                },
+                ForceField::Wind { min, max, velocity, coefficient } => {
+                    res.push(3);
+                    push_v3(&mut res, min);
+                    push_v3(&mut res, max);
+                    push_v3(&mut res, velocity);
+                    res.extend_from_slice(&coefficient.to_le_bytes());
+                },
+            }
+        }
+
+        res.push(rule_to_byte(self.materials.restitution_rule));
+        res.push(rule_to_byte(self.materials.friction_rule));
+        res.extend_from_slice(&(self.materials.count() as u64).to_le_bytes());
+        for m in &self.materials.materials {
+            for v in [m.restitution, m.static_friction, m.dynamic_friction, m.density] {
+                res.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+
+        res.extend_from_slice(&(self.spheres.len() as u64).to_le_bytes());
+        for s in &self.spheres {
+            res.extend_from_slice(&(s.id as u64).to_le_bytes());
+            push_v3(&mut res, &s.pos);
+            push_v3(&mut res, &s.vel);
+            push_rotation(&mut res, &s.orientation);
+            push_v3(&mut res, &s.ang_vel);
+            res.extend_from_slice(&s.radius.to_le_bytes());
+            res.extend_from_slice(&s.mass.to_le_bytes());
+            res.extend_from_slice(&(s.material as u64).to_le_bytes());
+        }
+
+        res.extend_from_slice(&(self.walls.len() as u64).to_le_bytes());
+        for w in &self.walls {
+            push_v3(&mut res, &w.center);
+            push_v3(&mut res, &w.size);
+            push_rotation(&mut res, &w.rotation);
+            res.extend_from_slice(&(w.material as u64).to_le_bytes());
+        }
+
+        res.extend_from_slice(&(self.planes.len() as u64).to_le_bytes());
+        for p in &self.planes {
+            push_v3(&mut res, &p.point);
+            push_v3(&mut res, &p.normal);
+            res.push(p.one_sided as u8);
+            res.extend_from_slice(&(p.material as u64).to_le_bytes());
+        }
+
+        res
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut r = Reader::new(bytes, SnapshotError::Binary);
+
+        if r.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(SnapshotError::Binary("missing header".to_string()));
+        }
+
+        let version = r.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let next_id = r.u64()? as usize;
+
+        let mut config = SimConfig::default();
+        if version >= 6 {
+            config.gravity = r.v3()?;
+            let field_count = r.u64()? as usize;
+            config.fields.reserve(field_count.min(bytes.len()));
+            for _ in 0..field_count {
+                config.fields.push(r.field()?);
+            }
+        }
+
+        let mut materials = Materials::new();
+        if version >= 5 {
+            materials.restitution_rule = r.rule()?;
+            materials.friction_rule = r.rule()?;
+            let material_count = r.u64()? as usize;
+            materials.materials.clear();
+            materials.materials.reserve(material_count.min(bytes.len()));
+            for _ in 0..material_count {
+                materials.materials.push(Material {
+                    restitution: r.f32()?,
+                    static_friction: r.f32()?,
+                    dynamic_friction: r.f32()?,
+                    density: r.f32()?,
+                });
+            }
+        }
+
+        // materials were added in version 5, older entities use the default materials
+        let material = |r: &mut Reader<SnapshotError>, default: MaterialId| -> Result<usize, SnapshotError> {
+            if version >= 5 { Ok(r.u64()? as usize) } else { Ok(default) }
+        };
+
+        let sphere_count = r.u64()? as usize;
+        let mut spheres = Vec::with_capacity(sphere_count.min(bytes.len()));
+        for _ in 0..sphere_count {
+            spheres.push(SphereSnapshot {
+                id: r.u64()? as usize,
+                pos: r.v3()?,
+                vel: r.v3()?,
+                orientation: if version >= 4 { r.rotation()? } else { Rotation::identity() },
+                ang_vel: if version >= 4 { r.v3()? } else { vector![0.0, 0.0, 0.0] },
+                radius: r.f32()?,
+                mass: r.f32()?,
+                material: material(&mut r, DEFAULT_MATERIAL)?,
+            });
+        }
+
+        let wall_count = r.u64()? as usize;
+        let mut walls = Vec::with_capacity(wall_count.min(bytes.len()));
+        for _ in 0..wall_count {
+            walls.push(WallSnapshot {
+                center: r.v3()?,
+                size: r.v3()?,
+                rotation: if version >= 3 { r.rotation()? } else { Rotation::identity() },
+                material: material(&mut r, WALL_MATERIAL)?,
+            });
+        }
+
+        let mut planes = vec![];
+        if version >= 2 {
+            let plane_count = r.u64()? as usize;
+            planes.reserve(plane_count.min(bytes.len()));
+            for _ in 0..plane_count {
+                planes.push(PlaneSnapshot {
+                    point: r.v3()?,
+                    normal: r.v3()?,
+                    one_sided: match r.take(1)?[0] {
+                        0 => false,
+                        1 => true,
+                        b => return Err(SnapshotError::Binary(format!("invalid one_sided value {}", b)))
+                    },
+                    material: material(&mut r, DEFAULT_MATERIAL)?,
+                });
+            }
+        }
+
+        if r.offset != bytes.len() {
+            return Err(SnapshotError::Binary(format!("{} trailing bytes", bytes.len() - r.offset)));
+        }
+
+        let snapshot = Self { next_id, spheres, walls, planes, materials, config };
+        snapshot.check().map_err(SnapshotError::Binary)?;
+        Ok(snapshot)
+    }
+
+    // Sphere ids must be unique and below next_id, otherwise the next added sphere reuses an id.
+    // Radius and mass must be finite and greater than 0, they are divided by in the inertia and impulses.
+    // Rotations and plane normals must be unit length and material ids must point into the material table
+    fn check(&self) -> Result<(), String> {
+        let mut ids: Vec::<usize> = self.spheres.iter().map(|s| s.id).collect();
+        ids.sort_unstable();
+        if let Some(w) = ids.windows(2).find(|w| w[0] == w[1]) {
+            return Err(format!("duplicate sphere id {}", w[0]));
+        }
+        if let Some(&max) = ids.last() {
+            if self.next_id <= max {
+                return Err(format!("next_id {} is not greater than sphere id {}", self.next_id, max));
+            }
+        }
+
+        for s in &self.spheres {
+            if !(s.radius.is_finite() && s.radius > 0.0) {
+                return Err(format!("sphere {} radius {} is not greater than 0", s.id, s.radius));
+            }
+            if !(s.mass.is_finite() && s.mass > 0.0) {
+                return Err(format!("sphere {} mass {} is not greater than 0", s.id, s.mass));
+            }
+            if !is_unit_rotation(&s.orientation) {
+                return Err(format!("sphere {} orientation {} is not unit length", s.id, fmt_rotation(&s.orientation)));
+            }
+        }
+
+        for w in &self.walls {
+            if !w.size.iter().all(|v| v.is_finite() && *v > 0.0) {
+                return Err(format!("wall size {} is not greater than 0 in every component", fmt_v3(&w.size)));
+            }
+            if !is_unit_rotation(&w.rotation) {
+                return Err(format!("wall rotation {} is not unit length", fmt_rotation(&w.rotation)));
+            }
+        }
+
+        if let Some(p) = self.planes.iter().find(|p| !is_unit(&p.normal)) {
+            return Err(format!("plane normal {} is not unit length", fmt_v3(&p.normal)));
+        }
+
+        let count = self.materials.count();
+        if count == 0 {
+            return Err("no materials".to_string());
+        }
+
+        let ids = self.spheres.iter().map(|s| s.material)
+            .chain(self.walls.iter().map(|w| w.material))
+            .chain(self.planes.iter().map(|p| p.material));
+
+        for id in ids {
+            if id >= count {
+                return Err(format!("material {} does not exist, there are {} materials", id, count));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+fn fmt_v3(v: &V3) -> String {
+    format!("{},{},{}", v.x, v.y, v.z)
+}
+
+fn parse_v3(s: &str) -> Option<V3> {
+    let mut parts = s.split(',').map(|p| p.parse::<f32>());
+    let v = vector![parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?];
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(v)
+}
+
+// stored quaternions are already unit length, so they are not normalized again. That would change the bits.
+// check rejects the ones that are not
+fn parse_rotation(s: &str) -> Option<Rotation> {
+    let mut parts = s.split(',').map(|p| p.parse::<f32>());
+    let (i, j, k, w) = (parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?, parts.next()?.ok()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Rotation::new_unchecked(na::Quaternion::new(w, i, j, k)))
+}
+
+fn is_unit_rotation(r: &Rotation) -> bool {
+    (r.quaternion().norm_squared() - 1.0).abs() <= 1e-5
+}
+
+fn fmt_rotation(r: &Rotation) -> String {
+    let q = r.quaternion();
+    format!("{},{},{},{}", q.i, q.j, q.k, q.w)
+}
+
+fn push_rotation(res: &mut Vec::<u8>, r: &Rotation) {
+    let q = r.quaternion();
+    for c in [q.i, q.j, q.k, q.w] {
+        res.extend_from_slice(&c.to_le_bytes());
+    }
+}
+
+fn rule_to_byte(rule: CombineRule) -> u8 {
+    match rule {
+        CombineRule::Min => 0,
+        CombineRule::Max => 1,
+        CombineRule::Average => 2,
+        CombineRule::Multiply => 3,
+    }
+}
+
+fn push_v3(res: &mut Vec::<u8>, v: &V3) {
+    res.extend_from_slice(&v.x.to_le_bytes());
+    res.extend_from_slice(&v.y.to_le_bytes());
+    res.extend_from_slice(&v.z.to_le_bytes());
+}
+
+
+// Little endian reader over a byte slice, also used for trajectories, error builds the error of the format being read
+pub(super) struct Reader<'a, E> {
+    bytes: &'a [u8],
+    offset: usize,
+    error: fn(String) -> E,
+}
+
+impl<'a, E> Reader<'a, E> {
+    pub(super) fn new(bytes: &'a [u8], error: fn(String) -> E) -> Self {
+        Self { bytes, offset: 0, error }
+    }
+
+    pub(super) fn at_end(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+
+    pub(super) fn take(&mut self, n: usize) -> Result<&'a [u8], E> {
+        if self.bytes.len() - self.offset < n {
+            return Err((self.error)(format!("unexpected end of data at byte {}", self.offset)));
+        }
+        let res = &self.bytes[self.offset..self.offset + n];
+        self.offset += n;
+        Ok(res)
+    }
+
+    pub(super) fn u32(&mut self) -> Result<u32, E> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(super) fn u64(&mut self) -> Result<u64, E> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(super) fn f32(&mut self) -> Result<f32, E> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(super) fn v3(&mut self) -> Result<V3, E> {
+        Ok(vector![self.f32()?, self.f32()?, self.f32()?])
+    }
+}
+
+impl Reader<'_, SnapshotError> {
+    fn rule(&mut self) -> Result<CombineRule, SnapshotError> {
+        match self.take(1)?[0] {
+            0 => Ok(CombineRule::Min),
+            1 => Ok(CombineRule::Max),
+            2 => Ok(CombineRule::Average),
+            3 => Ok(CombineRule::Multiply),
+            b => Err(SnapshotError::Binary(format!("invalid combine rule {}", b)))
+        }
+    }
+
+    fn field(&mut self) -> Result<ForceField, SnapshotError> {
+        match self.take(1)?[0] {
+            0 => Ok(ForceField::Uniform { force: self.v3()? }),
+            1 => Ok(ForceField::Attractor { center: self.v3()?, strength: self.f32()?, min_dist: self.f32()? }),
+            2 => Ok(ForceField::Drag { coefficient: self.f32()? }),
+            3 => Ok(ForceField::Wind { min: self.v3()?, max: self.v3()?, velocity: self.v3()?, coefficient: self.f32()? }),
+            b => Err(SnapshotError::Binary(format!("invalid force field {}", b)))
+        }
+    }
+
+    fn rotation(&mut self) -> Result<Rotation, SnapshotError> {
+        let (i, j, k, w) = (self.f32()?, self.f32()?, self.f32()?, self.f32()?);
+        Ok(Rotation::new_unchecked(na::Quaternion::new(w, i, j, k)))
+    }
+}
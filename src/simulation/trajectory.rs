@@ -0,0 +1,225 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use nalgebra::vector;
+
+use super::{Simulation, EntityId, V3};
+use super::snapshot::{Format, Reader};
+
+// Trajectory log, every sphere's id, position and velocity, written while stepping and read back to replay a run.
+// Text is csv with one row per sphere per recorded step:
+//   step,id,x,y,z,vx,vy,vz
+// Binary is little endian, after the magic and version each recorded step is
+//   step u64, count u64, then count times id u64, position 3 x f32, velocity 3 x f32
+// Floats are written with rust's shortest round trip formatting, like snapshots, so both formats hold the exact values.
+
+// 1: initial
+pub const VERSION: u32 = 1;
+
+pub const CSV_HEADER: &str = "step,id,x,y,z,vx,vy,vz";
+pub const BINARY_MAGIC: &[u8; 6] = b"DDTRAJ";
+
+
+pub struct Recorder {
+    out: BufWriter<File>,
+    format: Format,
+    // record every nth step
+    every: usize,
+    // steps seen so far, step 0 is the state before the first step
+    step: usize,
+}
+
+impl Recorder {
+
+    /// Creates the file at path, .bin is binary, everything else csv. Records every nth step, every 0 is treated as 1
+    pub fn create(path: &str, every: usize) -> io::Result<Self> {
+        let format = Format::from_path(path);
+        let mut out = BufWriter::new(File::create(path)?);
+
+        match format {
+            Format::Text => writeln!(out, "{}", CSV_HEADER)?,
+            Format::Binary => {
+                out.write_all(BINARY_MAGIC)?;
+                out.write_all(&VERSION.to_le_bytes())?;
+            }
+        }
+
+        Ok(Self { out, format, every: every.max(1), step: 0 })
+    }
+
+    /// Call once before the first step and once after every step, writes the ones that fall on every
+    pub fn record(&mut self, state: &dyn Simulation) -> io::Result<()> {
+        let step = self.step;
+        self.step += 1;
+
+        if !step.is_multiple_of(self.every) {
+            return Ok(());
+        }
+
+        let ids = state.sphere_ids();
+        let positions = state.sphere_positions();
+        let velocities = state.sphere_velocities();
+
+        match self.format {
+            Format::Text => {
+                for ((id, p), v) in ids.iter().zip(&positions).zip(&velocities) {
+                    writeln!(self.out, "{},{},{},{},{},{},{},{}", step, id, p.x, p.y, p.z, v.x, v.y, v.z)?;
+                }
+            },
+            Format::Binary => {
+                self.out.write_all(&(step as u64).to_le_bytes())?;
+                self.out.write_all(&(ids.len() as u64).to_le_bytes())?;
+                for ((id, p), v) in ids.iter().zip(&positions).zip(&velocities) {
+                    self.out.write_all(&(*id as u64).to_le_bytes())?;
+                    for c in [p.x, p.y, p.z, v.x, v.y, v.z] {
+                        self.out.write_all(&c.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+
+/// One recorded step
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub step: usize,
+    pub ids: Vec::<EntityId>,
+    pub positions: Vec::<V3>,
+    pub velocities: Vec::<V3>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trajectory {
+    /// in the order they were recorded
+    pub frames: Vec::<Frame>,
+}
+
+
+#[derive(Debug)]
+pub enum TrajectoryError {
+    Io(io::Error),
+    UnsupportedVersion(u32),
+    Parse { line: usize, msg: String },
+    Binary(String),
+}
+
+impl fmt::Display for TrajectoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrajectoryError::Io(err) => write!(f, "{}", err),
+            TrajectoryError::UnsupportedVersion(v) => write!(f, "unsupported trajectory version {}, expected 1 to {}", v, VERSION),
+            TrajectoryError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            TrajectoryError::Binary(msg) => write!(f, "invalid binary trajectory: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TrajectoryError {}
+
+impl From<io::Error> for TrajectoryError {
+    fn from(err: io::Error) -> Self {
+        TrajectoryError::Io(err)
+    }
+}
+
+
+impl Trajectory {
+
+    // Format is detected from the content, not the file name
+    pub fn read(path: &str) -> Result<Self, TrajectoryError> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(BINARY_MAGIC) {
+            Self::from_bytes(&bytes)
+        } else {
+            let text = String::from_utf8(bytes).map_err(|_| TrajectoryError::Parse { line: 1, msg: "not valid utf-8".to_string() })?;
+            Self::from_csv(&text)
+        }
+    }
+
+    /// Rows of the same step make one frame, steps must increase
+    pub fn from_csv(src: &str) -> Result<Self, TrajectoryError> {
+        let mut lines = src.lines().enumerate().map(|(i, l)| (i + 1, l.trim())).filter(|(_, l)| !l.is_empty());
+
+        let err = |line: usize, msg: String| TrajectoryError::Parse { line, msg };
+
+        match lines.next() {
+            Some((_, CSV_HEADER)) => {},
+            Some((line, _)) => return Err(err(line, format!("expected header '{}'", CSV_HEADER))),
+            None => return Err(err(1, "empty trajectory".to_string())),
+        }
+
+        let mut frames: Vec::<Frame> = vec![];
+        for (line, text) in lines {
+            let fields: Vec::<&str> = text.split(',').map(|f| f.trim()).collect();
+            if fields.len() != 8 {
+                return Err(err(line, format!("expected 8 fields, found {}", fields.len())));
+            }
+
+            let step = fields[0].parse::<usize>().map_err(|_| err(line, format!("invalid step '{}'", fields[0])))?;
+            let id = fields[1].parse::<EntityId>().map_err(|_| err(line, format!("invalid id '{}'", fields[1])))?;
+            let mut values = [0.0; 6];
+            for (v, f) in values.iter_mut().zip(&fields[2..]) {
+                *v = f.parse::<f32>().map_err(|_| err(line, format!("expected a number, found '{}'", f)))?;
+            }
+
+            let frame = match frames.last_mut() {
+                Some(frame) if frame.step == step => frame,
+                Some(frame) if frame.step > step => return Err(err(line, format!("step {} after step {}", step, frame.step))),
+                _ => {
+                    frames.push(Frame { step, ids: vec![], positions: vec![], velocities: vec![] });
+                    frames.last_mut().unwrap()
+                }
+            };
+
+            frame.ids.push(id);
+            frame.positions.push(vector![values[0], values[1], values[2]]);
+            frame.velocities.push(vector![values[3], values[4], values[5]]);
+        }
+
+        Ok(Self { frames })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TrajectoryError> {
+        let mut r = Reader::new(bytes, TrajectoryError::Binary);
+
+        if r.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(TrajectoryError::Binary("missing header".to_string()));
+        }
+
+        let version = r.u32()?;
+        if version == 0 || version > VERSION {
+            return Err(TrajectoryError::UnsupportedVersion(version));
+        }
+
+        let mut frames: Vec::<Frame> = vec![];
+        while !r.at_end() {
+            let step = r.u64()? as usize;
+            if let Some(last) = frames.last() {
+                if last.step >= step {
+                    return Err(TrajectoryError::Binary(format!("step {} after step {}", step, last.step)));
+                }
+            }
+
+            // a corrupt count fails on the first missing sphere, not on the allocation
+            let count = (r.u64()? as usize).min(bytes.len() / 32);
+            let mut frame = Frame { step, ids: Vec::with_capacity(count), positions: Vec::with_capacity(count), velocities: Vec::with_capacity(count) };
+            for _ in 0..count {
+                frame.ids.push(r.u64()? as EntityId);
+                frame.positions.push(r.v3()?);
+                frame.velocities.push(r.v3()?);
+            }
+            frames.push(frame);
+        }
+
+        Ok(Self { frames })
+    }
+}
+
@@ -0,0 +1,23 @@
+use std::process::Command;
+
+// The bench subcommand, run as the binary since it is not part of the library
+
+
+fn bench(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_DataDrivenSimulation")).arg("bench").args(args).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn duration_shorter_than_a_step_still_steps_once() {
+    let json = bench(&["--balls", "4", "--duration", "0.000000001", "--json"]);
+    assert!(json.contains("\"steps\": 1,"), "{}", json);
+    assert!(!json.contains("NaN") && !json.contains("inf"), "{}", json);
+}
+
+#[test]
+fn runs_the_given_steps() {
+    let json = bench(&["--balls", "4", "--steps", "3", "--json"]);
+    assert!(json.contains("\"steps\": 3,"), "{}", json);
+}
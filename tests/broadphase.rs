@@ -0,0 +1,184 @@
+use nalgebra::vector;
+
+use data_driven_simulation::simulation::V3;
+use data_driven_simulation::simulation::broadphase::{Broadphase, BroadphaseKind, AllPairs, WallIndex, BROADPHASES};
+use data_driven_simulation::simulation::colliders::Walls;
+
+// Every broadphase must give exactly the pairs AllPairs gives, sorted and unique, also after spheres moved
+// or were added since the last update and for spheres that are NaN, infinite or huge
+
+
+// small xorshift, so the scenes are the same on every run without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // in [min, max)
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (self.next() >> 40) as f32 / (1u64 << 24) as f32 * (max - min)
+    }
+
+    fn v3(&mut self, size: f32) -> V3 {
+        vector![self.range(-size, size), self.range(-size, size), self.range(-size, size)]
+    }
+}
+
+fn kinds() -> Vec::<BroadphaseKind> {
+    let mut res = BROADPHASES.to_vec();
+    res.push(BroadphaseKind::QuadTree { scale: 0.1 });
+    res.push(BroadphaseKind::QuadTree { scale: 10.0 });
+    res.push(BroadphaseKind::Grid { cell_size: 0.5 });
+    res.push(BroadphaseKind::Grid { cell_size: 20.0 });
+    res
+}
+
+fn check(name: &str, steps: &[(Vec::<V3>, Vec::<f32>)]) {
+    for kind in kinds() {
+        let mut broadphase = kind.create();
+        let mut pairs = vec![];
+        let mut expected = vec![];
+
+        for (step, (positions, radius)) in steps.iter().enumerate() {
+            AllPairs::new().pairs(positions, radius, &mut expected);
+
+            broadphase.update(positions, radius);
+            broadphase.pairs(positions, radius, &mut pairs);
+            assert_eq!(pairs, expected, "{} with {} at step {}", name, kind, step);
+        }
+    }
+}
+
+// spheres moving a bit each step, with a few added every step
+fn moving(rng: &mut Rng, count: usize, size: f32, min_radius: f32, max_radius: f32) -> Vec::<(Vec::<V3>, Vec::<f32>)> {
+    let mut positions: Vec::<V3> = (0..count).map(|_| rng.v3(size)).collect();
+    let mut radius: Vec::<f32> = (0..count).map(|_| rng.range(min_radius, max_radius)).collect();
+
+    let mut res = vec![];
+    for _ in 0..10 {
+        res.push((positions.clone(), radius.clone()));
+
+        for pos in positions.iter_mut() {
+            *pos += rng.v3(size * 0.05);
+        }
+        for _ in 0..count / 20 {
+            positions.push(rng.v3(size));
+            radius.push(rng.range(min_radius, max_radius));
+        }
+    }
+    res
+}
+
+
+#[test]
+fn random_spheres() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    check("dense", &moving(&mut rng, 400, 20.0, 0.5, 1.5));
+    check("sparse", &moving(&mut rng, 300, 200.0, 0.1, 3.0));
+    check("mixed sizes", &moving(&mut rng, 300, 50.0, 0.01, 15.0));
+}
+
+#[test]
+fn flat_and_touching_spheres() {
+    // all at z = 0 on a grid, exactly touching, like the grid preset
+    let mut positions = vec![];
+    let mut radius = vec![];
+    for x in -10..10 {
+        for y in -10..10 {
+            positions.push(vector![x as f32, y as f32, 0.0]);
+            radius.push(0.5);
+        }
+    }
+    check("touching", &[(positions.clone(), radius.clone())]);
+
+    // the same spheres on top of each other
+    let same = vec![vector![1.0, 2.0, 3.0]; 20];
+    check("same position", &[(same, vec![1.0; 20])]);
+}
+
+#[test]
+fn non_finite_and_huge_spheres() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut steps = moving(&mut rng, 200, 30.0, 0.5, 2.0);
+
+    for (step, (positions, radius)) in steps.iter_mut().enumerate() {
+        positions[3] = vector![f32::NAN, 0.0, 0.0];
+        positions[7] = vector![f32::INFINITY, 1.0, 1.0];
+        positions[11] = vector![f32::NEG_INFINITY, f32::INFINITY, 0.0];
+        radius[13] = 1e4;
+        radius[17] = 0.0;
+        radius[19] = f32::NAN;
+        positions[23] = vector![1e7, -1e7, 1e7];
+        // a sphere that blows up and comes back
+        if step % 2 == 0 {
+            positions[29] = vector![0.0, f32::NAN, 0.0];
+        }
+    }
+    check("non finite and huge", &steps);
+
+    // infinite radius overlaps everything that is finite, and infinite positions on the same side
+    let mut steps = moving(&mut rng, 50, 10.0, 0.5, 1.0);
+    for (positions, radius) in steps.iter_mut() {
+        radius[0] = f32::INFINITY;
+        positions[5] = vector![f32::INFINITY, 0.0, 0.0];
+    }
+    check("infinite radius", &steps);
+}
+
+#[test]
+fn fewer_spheres_than_last_update() {
+    let mut rng = Rng(0x1234_5678_9abc_def1);
+    let mut steps = moving(&mut rng, 200, 20.0, 0.5, 1.5);
+    let (positions, radius) = steps[0].clone();
+    steps.push((positions[..50].to_vec(), radius[..50].to_vec()));
+    steps.push((vec![], vec![]));
+    steps.push((positions, radius));
+    check("fewer spheres", &steps);
+}
+
+
+#[test]
+fn wall_index_follows_wall_changes() {
+    let mut walls = Walls::new();
+    walls.add_wall(vector![0.0, 0.0, 0.0], vector![2.0, 2.0, 2.0]);
+    walls.add_wall(vector![-20.0, 0.0, 0.0], vector![2.0, 2.0, 2.0]);
+
+    let mut index = WallIndex::new();
+    let mut near = vec![];
+    let (min, max) = (vector![9.0, -1.0, -1.0], vector![11.0, 1.0, 1.0]);
+
+    index.update(&walls);
+    index.query(&min, &max, &mut near);
+    assert!(near.is_empty());
+
+    walls.set_position(0, vector![10.0, 0.0, 0.0]);
+    index.update(&walls);
+    index.query(&min, &max, &mut near);
+    assert_eq!(near, vec![0]);
+
+    walls.set_position(0, vector![0.0, 0.0, 0.0]);
+    walls.set_size(1, vector![70.0, 2.0, 2.0]);
+    index.update(&walls);
+    index.query(&min, &max, &mut near);
+    assert_eq!(near, vec![1]);
+
+    walls.set_size(1, vector![2.0, 2.0, 2.0]);
+    walls.set_rotation(0, nalgebra::UnitQuaternion::from_axis_angle(&nalgebra::Vector3::z_axis(), 0.1));
+    walls.set_position(0, vector![10.0, 0.0, 0.0]);
+    index.update(&walls);
+    index.query(&min, &max, &mut near);
+    assert_eq!(near, vec![0]);
+
+    // a different set of walls with the same count
+    let mut other = Walls::new();
+    other.add_wall(vector![0.0, 0.0, 0.0], vector![2.0, 2.0, 2.0]);
+    other.add_wall(vector![10.0, 0.0, 0.0], vector![2.0, 2.0, 2.0]);
+    index.update(&other);
+    index.query(&min, &max, &mut near);
+    assert_eq!(near, vec![1]);
+}
@@ -0,0 +1,29 @@
+use data_driven_simulation::simulation::Simulation;
+use data_driven_simulation::simulation::datadriven::{State, Narrowphase};
+use data_driven_simulation::presets;
+
+// The lanes narrowphase must give the same state bit for bit as testing one pair at a time
+
+
+const STEPS: usize = 20;
+const DT: f32 = 0.01;
+
+fn run(build: presets::Build, narrowphase: Narrowphase) -> Vec::<u8> {
+    let mut state = State::new();
+    state.narrowphase = narrowphase;
+    build(&mut state);
+
+    for _ in 0..STEPS {
+        state.step(DT);
+    }
+    state.snapshot().to_bytes()
+}
+
+#[test]
+fn lanes_match_scalar() {
+    for &(name, build) in presets::PRESETS {
+        let scalar = run(build, Narrowphase::Scalar);
+        let lanes = run(build, Narrowphase::Lanes);
+        assert!(scalar == lanes, "{}", name);
+    }
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "parallel")]
+
+use nalgebra::vector;
+
+use data_driven_simulation::simulation::Simulation;
+use data_driven_simulation::simulation::datadriven::{State, Narrowphase, NARROWPHASES};
+use data_driven_simulation::presets;
+
+// The parallel datadriven step must give the same state bit for bit as the single threaded one
+
+
+const STEPS: usize = 20;
+const DT: f32 = 0.01;
+
+fn run(build: presets::Build, floor: bool, parallel: bool, narrowphase: Narrowphase) -> Vec::<u8> {
+    let mut state = State::new();
+    state.parallel = parallel;
+    state.narrowphase = narrowphase;
+    build(&mut state);
+
+    // gravity and a plane, so the force and plane passes do something
+    if floor {
+        state.set_gravity(vector![0.0, -9.81, 0.0]);
+        state.add_plane(vector![0.0, -20.0, 0.0], vector![0.0, 1.0, 0.0], false);
+    }
+
+    for _ in 0..STEPS {
+        state.step(DT);
+    }
+    state.snapshot().to_bytes()
+}
+
+#[test]
+fn parallel_matches_single_threaded() {
+    for &(name, build) in presets::PRESETS {
+        for &narrowphase in NARROWPHASES {
+            for floor in [false, true] {
+                let serial = run(build, floor, false, narrowphase);
+                let parallel = run(build, floor, true, narrowphase);
+                assert!(serial == parallel, "{} with {} narrowphase, floor {}", name, narrowphase, floor);
+            }
+        }
+    }
+}
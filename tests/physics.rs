@@ -0,0 +1,96 @@
+use nalgebra::{vector, UnitQuaternion, Vector3};
+use data_driven_simulation::simulation::BACKENDS;
+use data_driven_simulation::simulation::friction::Friction;
+
+// Contacts with rotated walls, spheres inside walls and friction, on both backends
+
+
+const DT: f32 = 0.01;
+
+#[test]
+fn rotated_wall_bounces_along_its_normal() {
+    let rotation = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f32::consts::FRAC_PI_4);
+    let normal = rotation * vector![0.0, 1.0, 0.0];
+
+    for &backend in BACKENDS {
+        let mut state = backend.new_state();
+        // without friction the whole change in velocity is along the normal
+        for material in state.materials_mut().materials.iter_mut() {
+            material.static_friction = 0.0;
+            material.dynamic_friction = 0.0;
+        }
+
+        state.add_rotated_wall(vector![0.0, 0.0, 0.0], vector![20.0, 1.0, 20.0], rotation);
+        let vel = vector![0.0, -5.0, 0.0];
+        state.add_ball(vector![0.0, 3.0, 0.0], vel, 1.0, 1.0);
+
+        for _ in 0..100 {
+            state.step(DT);
+        }
+
+        let change = state.sphere_velocities()[0] - vel;
+        assert!(change.dot(&normal) > 5.0, "{}: {:?}", backend, change);
+        assert!((change - change.dot(&normal) * normal).norm() < 1e-3, "{}: {:?}", backend, change);
+    }
+}
+
+#[test]
+fn sphere_inside_wall_is_pushed_out() {
+    for &backend in BACKENDS {
+        let mut state = backend.new_state();
+        state.add_wall(vector![0.0, 0.0, 0.0], vector![4.0, 4.0, 4.0]);
+        // closest to the +x face
+        state.add_ball(vector![1.5, 0.2, -0.1], vector![0.0, 0.0, 0.0], 0.5, 1.0);
+
+        for _ in 0..100 {
+            state.step(DT);
+        }
+
+        let pos = state.sphere_positions()[0];
+        let (_, dist) = state.walls().contact(0, &pos);
+        assert!(dist >= 0.5 - 1e-3, "{}: {:?} is {} from the wall", backend, pos, dist);
+        assert!(pos.x > 2.0, "{}: {:?}", backend, pos);
+    }
+}
+
+#[test]
+fn sliding_sphere_starts_rolling() {
+    for &backend in BACKENDS {
+        let mut state = backend.new_state();
+        state.set_gravity(vector![0.0, -9.81, 0.0]);
+        state.add_plane(vector![0.0, 0.0, 0.0], vector![0.0, 1.0, 0.0], true);
+        state.add_ball(vector![0.0, 1.0, 0.0], vector![5.0, 0.0, 0.0], 1.0, 1.0);
+
+        for _ in 0..200 {
+            state.step(DT);
+        }
+
+        let vel = state.sphere_velocities()[0];
+        let ang_vel = state.sphere_angular_velocities()[0];
+
+        // friction slows the sliding and spins the sphere up, until the contact point is at rest
+        assert!(vel.x > 0.0 && vel.x < 5.0, "{}: {:?}", backend, vel);
+        assert!(ang_vel.z < 0.0, "{}: {:?}", backend, ang_vel);
+        let slip = vel + ang_vel.cross(&vector![0.0, -1.0, 0.0]);
+        assert!(slip.norm() < 1e-2, "{}: slip {:?}", backend, slip);
+    }
+}
+
+#[test]
+fn static_friction_holds_below_limit() {
+    let friction = Friction { static_coefficient: 0.5, dynamic_coefficient: 0.3 };
+
+    // below static_coefficient * normal impulse the sliding is stopped completely
+    assert_eq!(friction.impulse(0.4, 1.0), 0.4);
+    assert_eq!(friction.impulse(1.0, 2.0), 1.0);
+
+    // above it the contact slides with the dynamic coefficient
+    assert_eq!(friction.impulse(0.6, 1.0), 0.3);
+    assert_eq!(friction.impulse(10.0, 2.0), 0.6);
+}
+
+#[test]
+fn dynamic_friction_never_reverses_sliding() {
+    let friction = Friction { static_coefficient: 0.1, dynamic_coefficient: 0.8 };
+    assert_eq!(friction.impulse(0.5, 1.0), 0.5);
+}
@@ -0,0 +1,52 @@
+use data_driven_simulation::simulation::{Simulation, BACKENDS};
+use data_driven_simulation::presets;
+
+// The presets start without overlaps and collisions only take energy out of them
+
+
+const STEPS: usize = 200;
+const DT: f32 = 0.01;
+
+fn overlaps(state: &dyn Simulation) -> Option<(usize, usize)> {
+    let pos = state.sphere_positions();
+    let radius = state.sphere_radius();
+    for i in 0..pos.len() {
+        for j in (i + 1)..pos.len() {
+            if (pos[i] - pos[j]).norm() < radius[i] + radius[j] {
+                return Some((i, j));
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn spheres_start_apart() {
+    for &(name, build) in presets::PRESETS {
+        let mut state = BACKENDS[0].new_state();
+        build(state.as_mut());
+        assert_eq!(overlaps(state.as_ref()), None, "{}", name);
+    }
+}
+
+#[test]
+fn energy_never_exceeds_the_start() {
+    let builds: [(&str, presets::Build); 2] = [
+        ("grid", |state| presets::grid(20, 20, state)),
+        ("pool_break", |state| presets::pool_break(10, state)),
+    ];
+
+    for &backend in BACKENDS {
+        for (name, build) in builds {
+            let mut state = backend.new_state();
+            build(state.as_mut());
+            let start = state.diagnostics().total_energy();
+
+            for step in 0..STEPS {
+                state.step(DT);
+                let energy = state.diagnostics().total_energy();
+                assert!(energy <= start, "{} {}: energy {} at step {} is above {} at the start", name, backend, energy, step, start);
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use data_driven_simulation::scene;
+use data_driven_simulation::simulation::material::{DEFAULT_MATERIAL, WALL_MATERIAL};
+
+#[test]
+fn world_friction_sets_default_material() {
+    let state = scene::parse("world static_friction=0.7 dynamic_friction=0.2\nball pos=0,0,0 radius=1 mass=1\n", None).unwrap();
+    let material = state.materials().get(DEFAULT_MATERIAL);
+    assert_eq!(material.static_friction, 0.7);
+    assert_eq!(material.dynamic_friction, 0.2);
+}
+
+#[test]
+fn world_friction_sets_wall_material() {
+    let state = scene::parse("world static_friction=0.7 dynamic_friction=0.2\nwall center=0,0,0 size=1,1,1\n", None).unwrap();
+    assert_eq!(state.walls().materials(), &[WALL_MATERIAL]);
+    let material = state.materials().get(WALL_MATERIAL);
+    assert_eq!(material.restitution, 1.0);
+    assert_eq!(material.static_friction, 0.7);
+    assert_eq!(material.dynamic_friction, 0.2);
+}
+
+#[test]
+fn world_friction_is_optional() {
+    let state = scene::parse("world static_friction=0.7\n", None).unwrap();
+    let material = state.materials().get(DEFAULT_MATERIAL);
+    assert_eq!(material.static_friction, 0.7);
+    assert_eq!(material.dynamic_friction, data_driven_simulation::simulation::material::Material::default().dynamic_friction);
+}
+
+fn error(src: &str) -> String {
+    match scene::parse(src, None) {
+        Ok(_) => panic!("expected an error for {:?}", src),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn malformed_entries_report_their_line() {
+    let ball = "ball pos=0,0,0 radius=1\n";
+    let cases = [
+        ("ball pos=0,0,0 radius", "line 2: expected key=value, found 'radius'"),
+        ("ball pos=0,0,0 pos=1,0,0 radius=1", "line 2: 'pos' is given more than once"),
+        ("ball radius=1", "line 2: missing 'pos'"),
+        ("ball pos=0,0 radius=1", "line 2: 'pos' expects x,y,z, found '0,0'"),
+        ("ball pos=0,0,x radius=1", "line 2: 'pos' expects a number, found 'x'"),
+        ("ball pos=0,0,0 radius=inf", "line 2: 'radius' expects a number, found 'inf'"),
+        ("ball pos=0,0,0 radius=0", "line 2: 'radius' must be greater than 0, found 0"),
+        ("ball pos=0,0,0 radius=1 mass=-1", "line 2: 'mass' must be greater than 0, found -1"),
+        ("ball pos=0,0,0 radius=1 material=rubber", "line 2: unknown material 'rubber'"),
+        ("ball pos=0,0,0 radius=1 color=red", "line 2: unknown key 'color'"),
+        ("wall center=0,0,0 size=1,0,1", "line 2: 'size' must be greater than 0 in every component, found 1,0,1"),
+        ("wall center=0,0,0 size=1,1,-2", "line 2: 'size' must be greater than 0 in every component, found 1,1,-2"),
+        ("wall center=0,0,0 size=1,1,1 axis=0,0,0", "line 2: 'axis' must not be 0,0,0"),
+        ("plane point=0,0,0 normal=0,0,0", "line 2: 'normal' must not be 0,0,0"),
+        ("plane point=0,0,0 normal=0,1,0 one_sided=yes", "line 2: 'one_sided' expects true or false, found 'yes'"),
+        ("material name=rubber\nmaterial name=rubber", "line 3: material 'rubber' is already defined"),
+        ("material name=rubber restitution=-1", "line 2: 'restitution' must not be negative, found -1"),
+        ("world restitution_rule=sum", "line 2: 'restitution_rule' expects min, max, average or multiply, found 'sum'"),
+        ("drag coefficient=-0.1", "line 2: 'coefficient' must not be negative, found -0.1"),
+        ("backend name=oop\nbackend name=oop", "line 3: backend is given more than once"),
+        ("backend name=gpu", "line 2: unknown backend 'gpu', expected one of "),
+        ("broadphase name=bvh", "line 2: unknown broadphase 'bvh', expected one of "),
+        ("broadphase name=grid cell_size=0", "line 2: 'cell_size' must be greater than 0, found 0"),
+        ("box center=0,0,0", "line 2: unknown entry 'box', expected one of "),
+    ];
+
+    for (src, expected) in cases {
+        let msg = error(&format!("{}{}\n", ball, src));
+        assert!(msg.starts_with(expected), "{:?} gave {:?}, expected {:?}", src, msg, expected);
+    }
+}
+
+#[test]
+fn comments_and_empty_lines_count_as_lines() {
+    assert_eq!(error("# a comment\n\n   \nball pos=0,0,0\n"), "line 4: missing 'radius'");
+}
+
+#[test]
+fn valid_walls_load() {
+    let state = scene::parse("wall center=0,0,0 size=1,5,10 angle=30\n", None).unwrap();
+    assert_eq!(state.walls().count(), 1);
+}
@@ -0,0 +1,183 @@
+use data_driven_simulation::simulation::{Simulation, BACKENDS};
+use data_driven_simulation::simulation::snapshot::Snapshot;
+use data_driven_simulation::simulation::material::{DEFAULT_MATERIAL, WALL_MATERIAL};
+use data_driven_simulation::{presets, scene};
+
+// Snapshots restore a state exactly, in both formats and on both backends
+
+
+const SCENES: &[&str] = &["materials.scene", "ramp.scene", "rotated_walls.scene", "stacked.scene"];
+
+fn scene_path(name: &str) -> String {
+    format!("{}/scenes/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+// every preset and scene file on every backend, before and after some steps so spheres are spinning and touching
+fn states() -> Vec::<(String, Box<dyn Simulation>)> {
+    let mut res = vec![];
+    for &backend in BACKENDS {
+        for &(name, build) in presets::PRESETS {
+            let mut state = backend.new_state();
+            build(state.as_mut());
+            res.push((format!("{} {}", name, backend), state));
+        }
+
+        for name in SCENES {
+            let state = scene::load(&scene_path(name), Some(backend)).unwrap();
+            res.push((format!("{} {}", name, backend), state));
+        }
+    }
+
+    for (_, state) in res.iter_mut() {
+        for _ in 0..20 {
+            state.step(0.01);
+        }
+    }
+    res
+}
+
+#[test]
+fn text_round_trip() {
+    for (name, state) in states() {
+        let snapshot = state.snapshot();
+        assert_eq!(Snapshot::from_text(&snapshot.to_text()).unwrap(), snapshot, "{}", name);
+    }
+}
+
+#[test]
+fn binary_round_trip() {
+    for (name, state) in states() {
+        let snapshot = state.snapshot();
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot, "{}", name);
+    }
+}
+
+#[test]
+fn restored_state_matches() {
+    for (name, state) in states() {
+        let snapshot = state.snapshot();
+        let restored = state.backend().from_snapshot(&snapshot);
+        assert_eq!(restored.snapshot(), snapshot, "{}", name);
+    }
+}
+
+
+fn invalid(edit: impl Fn(&mut Snapshot), expected: &str) {
+    let mut state = BACKENDS[0].new_state();
+    state.add_plane(nalgebra::vector![0.0, 0.0, 0.0], nalgebra::vector![0.0, 1.0, 0.0], false);
+    presets::pool_break(3, state.as_mut());
+
+    let mut snapshot = state.snapshot();
+    edit(&mut snapshot);
+
+    let text = Snapshot::from_text(&snapshot.to_text()).unwrap_err().to_string();
+    assert!(text.contains(expected), "text: {}", text);
+    let binary = Snapshot::from_bytes(&snapshot.to_bytes()).unwrap_err().to_string();
+    assert!(binary.contains(expected), "binary: {}", binary);
+}
+
+#[test]
+fn rejects_duplicate_ids() {
+    invalid(|s| s.spheres[1].id = s.spheres[0].id, "duplicate sphere id");
+}
+
+#[test]
+fn rejects_next_id_not_above_ids() {
+    invalid(|s| s.next_id = s.spheres.iter().map(|s| s.id).max().unwrap(), "next_id");
+}
+
+#[test]
+fn rejects_non_unit_normal() {
+    invalid(|s| s.planes[0].normal *= 2.0, "not unit length");
+}
+
+#[test]
+fn rejects_non_unit_rotations() {
+    let zero = nalgebra::UnitQuaternion::new_unchecked(nalgebra::Quaternion::new(0.0, 0.0, 0.0, 0.0));
+    let scaled = nalgebra::UnitQuaternion::new_unchecked(nalgebra::Quaternion::new(2.0, 0.0, 0.0, 0.0));
+    let nan = nalgebra::UnitQuaternion::new_unchecked(nalgebra::Quaternion::new(f32::NAN, 0.0, 0.0, 0.0));
+
+    for rotation in [zero, scaled, nan] {
+        invalid(|s| s.spheres[0].orientation = rotation, "orientation");
+        invalid(|s| s.walls[0].rotation = rotation, "wall rotation");
+    }
+}
+
+#[test]
+fn rejects_bad_radius_and_mass() {
+    for v in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+        invalid(|s| s.spheres[0].radius = v, "radius");
+        invalid(|s| s.spheres[0].mass = v, "mass");
+    }
+}
+
+#[test]
+fn rejects_bad_wall_size() {
+    for v in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+        invalid(|s| s.walls[0].size.y = v, "wall size");
+    }
+}
+
+#[test]
+fn rejects_unknown_and_duplicate_keys() {
+    let mut state = BACKENDS[0].new_state();
+    presets::pool_break(3, state.as_mut());
+    let text = state.snapshot().to_text();
+
+    let sphere = text.lines().find(|l| l.starts_with("sphere ")).unwrap();
+    let next_id = text.lines().find(|l| l.starts_with("next_id ")).unwrap();
+    let edits = [
+        (sphere, format!("{} color=red", sphere), "unknown key 'color'"),
+        (sphere, format!("{} radius=2", sphere), "'radius' is given more than once"),
+        (next_id, format!("{} 20", next_id), "unexpected '20' after next_id"),
+    ];
+
+    for (line, edited, expected) in edits {
+        let err = Snapshot::from_text(&text.replacen(line, &edited, 1)).unwrap_err().to_string();
+        assert!(err.contains(expected), "{}: {}", expected, err);
+    }
+}
+
+#[test]
+fn keeps_gravity_and_force_fields() {
+    let src = "world gravity=0,-9.81,0.5\n\
+               uniform force=1,0,0\n\
+               attractor center=0,5,0 strength=10 min_dist=0.5\n\
+               drag coefficient=0.1\n\
+               wind min=-5,-5,-5 max=5,5,5 velocity=2,0,0 coefficient=0.5\n\
+               ball pos=0,0,0 radius=1 mass=1\n";
+
+    for &backend in BACKENDS {
+        let state = scene::parse(src, Some(backend)).unwrap();
+        let snapshot = state.snapshot();
+        assert_eq!(&snapshot.config, state.config());
+
+        assert_eq!(Snapshot::from_text(&snapshot.to_text()).unwrap(), snapshot);
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+
+        for &other in BACKENDS {
+            assert_eq!(other.from_snapshot(&snapshot).config(), state.config(), "{} to {}", backend, other);
+        }
+    }
+}
+
+#[test]
+fn older_versions_have_no_forces() {
+    let snapshot = Snapshot::from_text("snapshot 5\nnext_id 2\nrules restitution=multiply friction=average\n\
+                                        material restitution=0.9 static_friction=0.5 dynamic_friction=0.3 density=1\n\
+                                        sphere id=1 pos=0,0,0 vel=1,0,0 orientation=0,0,0,1 ang_vel=0,0,0 radius=1 mass=1 material=0\n").unwrap();
+    assert_eq!(snapshot.config, Default::default());
+    assert!(Snapshot::from_text("snapshot 5\nnext_id 1\ngravity value=0,-1,0\n").is_err());
+}
+
+#[test]
+fn older_versions_use_the_default_materials() {
+    let snapshot = Snapshot::from_text("snapshot 4\nnext_id 2\n\
+                                        sphere id=1 pos=0,0,0 vel=1,0,0 orientation=0,0,0,1 ang_vel=0,0,0 radius=1 mass=1\n\
+                                        wall center=0,5,0 size=1,1,1 rotation=0,0,0,1\n\
+                                        plane point=0,0,0 normal=0,1,0 one_sided=true\n").unwrap();
+    assert_eq!(snapshot.spheres[0].material, DEFAULT_MATERIAL);
+    assert_eq!(snapshot.walls[0].material, WALL_MATERIAL);
+    assert_eq!(snapshot.planes[0].material, DEFAULT_MATERIAL);
+    assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+}
@@ -0,0 +1,121 @@
+use data_driven_simulation::simulation::{Simulation, BACKENDS};
+use data_driven_simulation::simulation::trajectory::{Recorder, Trajectory, Frame, BINARY_MAGIC};
+use data_driven_simulation::presets;
+
+// Trajectories read back exactly what was recorded, in both formats, and broken files are rejected
+
+
+const STEPS: usize = 10;
+const EVERY: usize = 3;
+
+fn temp_path(name: &str) -> String {
+    format!("{}/trajectory_{}_{}", std::env::temp_dir().display(), std::process::id(), name)
+}
+
+fn frame(step: usize, state: &dyn Simulation) -> Frame {
+    Frame { step, ids: state.sphere_ids(), positions: state.sphere_positions(), velocities: state.sphere_velocities() }
+}
+
+// records a pool break to path and returns the frames that should have been written
+fn record(path: &str) -> Vec::<Frame> {
+    let mut state = BACKENDS[0].new_state();
+    presets::pool_break(5, state.as_mut());
+
+    let mut recorder = Recorder::create(path, EVERY).unwrap();
+    let mut expected = vec![];
+    for step in 0..=STEPS {
+        if step > 0 {
+            state.step(0.01);
+        }
+        recorder.record(state.as_ref()).unwrap();
+        if step.is_multiple_of(EVERY) {
+            expected.push(frame(step, state.as_ref()));
+        }
+    }
+    recorder.flush().unwrap();
+    expected
+}
+
+fn round_trip(name: &str) {
+    let path = temp_path(name);
+    let expected = record(&path);
+    let trajectory = Trajectory::read(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(trajectory.unwrap().frames, expected, "{}", name);
+}
+
+#[test]
+fn csv_round_trip() {
+    round_trip("round_trip.csv");
+}
+
+#[test]
+fn binary_round_trip() {
+    round_trip("round_trip.bin");
+}
+
+
+fn recorded(name: &str) -> Vec::<u8> {
+    let path = temp_path(name);
+    record(&path);
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    bytes
+}
+
+fn invalid_csv(text: &str, expected: &str) {
+    let err = Trajectory::from_csv(text).unwrap_err().to_string();
+    assert!(err.contains(expected), "{}: {}", expected, err);
+}
+
+fn invalid_bytes(bytes: &[u8], expected: &str) {
+    let err = Trajectory::from_bytes(bytes).unwrap_err().to_string();
+    assert!(err.contains(expected), "{}: {}", expected, err);
+}
+
+#[test]
+fn rejects_truncated_input() {
+    let text = String::from_utf8(recorded("truncated.csv")).unwrap();
+    let last = text.trim_end().lines().last().unwrap();
+    let cut = &last[..last.rfind(',').unwrap()];
+    invalid_csv(&text.replacen(last, cut, 1), "expected 8 fields, found 7");
+    invalid_csv("", "empty trajectory");
+
+    let bytes = recorded("truncated.bin");
+    for len in [bytes.len() - 1, bytes.len() - 20, BINARY_MAGIC.len() + 2] {
+        invalid_bytes(&bytes[..len], "unexpected end of data");
+    }
+}
+
+#[test]
+fn rejects_corrupt_input() {
+    let text = String::from_utf8(recorded("corrupt.csv")).unwrap();
+    let row = text.lines().nth(1).unwrap();
+    invalid_csv(&text.replacen(row, &row.replacen(',', ",x", 1), 1), "invalid id");
+    invalid_csv(&text.replacen(row, &format!("{}x", row), 1), "expected a number");
+    invalid_csv(&text.replacen("step,", "time,", 1), "expected header");
+    // a row of the first frame again after the last one
+    invalid_csv(&format!("{}{}\n", text, row), "step 0 after step 9");
+
+    let mut bytes = recorded("corrupt.bin");
+    bytes[0] = b'X';
+    invalid_bytes(&bytes, "missing header");
+    bytes[0] = BINARY_MAGIC[0];
+
+    // the step of the second frame set to the one of the first
+    let frame_len = 16 + bytes[BINARY_MAGIC.len() + 12] as usize * 32;
+    let second = BINARY_MAGIC.len() + 4 + frame_len;
+    bytes[second..second + 8].copy_from_slice(&0u64.to_le_bytes());
+    invalid_bytes(&bytes, "step 0 after step 0");
+}
+
+#[test]
+fn rejects_wrong_version() {
+    let mut bytes = recorded("version.bin");
+    let at = BINARY_MAGIC.len();
+    for version in [0u32, 2, u32::MAX] {
+        bytes[at..at + 4].copy_from_slice(&version.to_le_bytes());
+        invalid_bytes(&bytes, &format!("unsupported trajectory version {}", version));
+    }
+}